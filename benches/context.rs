@@ -0,0 +1,71 @@
+//! Compares the cost of `context`, `context_static`, `context_owned` and
+//! `context_light`, and of capturing backtraces with and without resolving
+//! their symbols right away. The `ok` entries measure the happy path, which
+//! must not capture anything.
+//!
+//! Run with `cargo bench --bench context`. `context` only captures a backtrace
+//! with `RUST_BACKTRACE=1`.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use n0_snafu::ResultExt;
+
+fn fail() -> Result<(), std::fmt::Error> {
+    Err(std::fmt::Error)
+}
+
+fn succeed() -> Result<(), std::fmt::Error> {
+    Ok(())
+}
+
+fn err(c: &mut Criterion) {
+    c.bench_function("context", |b| {
+        b.iter(|| black_box(fail()).context("probe").ok())
+    });
+    // the static message is borrowed instead of copied
+    c.bench_function("context_static", |b| {
+        b.iter(|| black_box(fail()).context_static("probe").ok())
+    });
+    // the message is copied once more by `context`, but moved by `context_owned`
+    c.bench_function("context String", |b| {
+        b.iter(|| {
+            let message = black_box(String::from("probe"));
+            black_box(fail()).context(message).ok()
+        })
+    });
+    c.bench_function("context_owned", |b| {
+        b.iter(|| {
+            let message = black_box(String::from("probe"));
+            black_box(fail()).context_owned(message).ok()
+        })
+    });
+    c.bench_function("with_context", |b| {
+        b.iter(|| black_box(fail()).with_context(|| "probe".into()).ok())
+    });
+    c.bench_function("e", |b| b.iter(|| black_box(fail()).e().ok()));
+    c.bench_function("context_light", |b| {
+        b.iter(|| black_box(fail()).context_light("probe").ok())
+    });
+}
+
+// the happy path is a plain move
+fn ok(c: &mut Criterion) {
+    c.bench_function("context ok", |b| {
+        b.iter(|| black_box(succeed()).context("probe").ok())
+    });
+    c.bench_function("with_context ok", |b| {
+        b.iter(|| black_box(succeed()).with_context(|| "probe".into()).ok())
+    });
+    c.bench_function("e ok", |b| b.iter(|| black_box(succeed()).e().ok()));
+}
+
+fn backtrace(c: &mut Criterion) {
+    c.bench_function("backtrace resolved", |b| b.iter(snafu::Backtrace::new));
+    c.bench_function("backtrace unresolved", |b| {
+        b.iter(snafu::Backtrace::new_unresolved)
+    });
+}
+
+criterion_group!(benches, err, ok, backtrace);
+criterion_main!(benches);
@@ -0,0 +1,86 @@
+use std::fmt::Display;
+
+use crate::{error::ChainLink, Error, ErrorCode};
+
+/// An [`Error`] as a [`miette::Diagnostic`].
+///
+/// `Diagnostic` requires `std::error::Error`, which [`Error`] does not implement,
+/// so errors are converted into a [`miette::Report`] instead.
+#[derive(Debug)]
+struct Diagnostic {
+    chain: ChainLink,
+    code: Option<ErrorCode>,
+    help: Option<String>,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.chain.fmt(f)
+    }
+}
+
+impl std::error::Error for Diagnostic {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        std::error::Error::source(&self.chain)
+    }
+}
+
+impl miette::Diagnostic for Diagnostic {
+    fn code<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        self.code.map(|code| Box::new(code) as Box<dyn Display>)
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        self.help
+            .as_ref()
+            .map(|help| Box::new(help) as Box<dyn Display>)
+    }
+}
+
+/// The messages of the chain become the report's causes. The error code is
+/// passed on as its code, the help notes and the first backtrace as its help.
+impl From<Error> for miette::Report {
+    fn from(err: Error) -> Self {
+        let help: Vec<String> = err.all_help().map(Into::into).collect();
+        #[cfg(feature = "backtrace")]
+        let help = {
+            let mut help = help;
+            if let Some(bt) = err.backtrace() {
+                let opts = crate::FormatOptions::plain();
+                help.push(opts.format_trace(&opts.printer(), &bt));
+            }
+            help
+        };
+        miette::Report::new(Diagnostic {
+            chain: ChainLink::new(&err),
+            code: err.code(),
+            help: (!help.is_empty()).then(|| help.join("\n")),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{format_err, ErrorCode, ResultExt};
+
+    #[test]
+    fn test_into_report() {
+        let err = Err::<(), _>(format_err!("inner"))
+            .context_code(ErrorCode::new("E42"), "outer")
+            .unwrap_err()
+            .with_help("try again");
+        let report = miette::Report::from(err);
+        assert_eq!(report.to_string(), "outer");
+        assert_eq!(report.code().unwrap().to_string(), "E42");
+        assert!(report.help().unwrap().to_string().starts_with("try again"));
+
+        let causes: Vec<_> = report.chain().skip(1).map(|e| e.to_string()).collect();
+        assert_eq!(causes, ["inner"]);
+
+        let mut rendered = String::new();
+        miette::NarratableReportHandler::new()
+            .render_report(&mut rendered, report.as_ref())
+            .unwrap();
+        assert!(rendered.contains("inner"), "{rendered}");
+    }
+}
@@ -1,11 +1,27 @@
-use color_backtrace::Verbosity;
+use std::{
+    borrow::Cow,
+    panic::Location,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, OnceLock, RwLock,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
 use snafu::{FromString, GenerateImplicitData, Snafu};
-use tracing_error::SpanTraceStatus;
 
-use crate::SpanTrace;
+#[cfg(feature = "backtrace")]
+use crate::format::frame_key;
+use crate::{format::truncate_message, FormatOptions, MessageStrings, SpanTrace, Verbosity};
 
 pub type Result<A = (), E = Error> = std::result::Result<A, E>;
 
+/// Creates an [`Error`] from a format string, without any source.
+///
+/// ```
+/// let err: n0_snafu::Error = n0_snafu::format_err!("x = {}", 1);
+/// assert_eq!(err.to_string(), "x = 1");
+/// ```
 #[macro_export]
 macro_rules! format_err {
     ($fmt:literal$(, $($arg:expr),* $(,)?)?) => {
@@ -18,6 +34,92 @@ macro_rules! format_err {
     };
 }
 
+/// Returns early with an [`Error`] if the condition does not hold.
+///
+/// The error, including its span trace and backtrace, is only constructed when
+/// the condition fails, so a passing check does no capture work.
+#[macro_export]
+macro_rules! ensure {
+    ($predicate:expr, $fmt:literal$(, $($arg:expr),* $(,)?)?) => {
+        if !$predicate {
+            return ::core::result::Result::Err($crate::format_err!($fmt$(, $($arg),*)*));
+        }
+    };
+}
+
+/// Adds context to a fallible expression and propagates the error, like `?`.
+///
+/// The recorded [`Error::location`] is the location of the `try_ctx!` invocation.
+#[macro_export]
+macro_rules! try_ctx {
+    ($expr:expr, $context:expr $(,)?) => {
+        $crate::ResultExt::context($expr, $context)?
+    };
+}
+
+/// Adds a formatted context message, which is only built on the error path.
+///
+/// `context_fmt!(res, "reading {}", path)` is short for
+/// `res.with_context(|| format!("reading {}", path))`.
+#[macro_export]
+macro_rules! context_fmt {
+    ($result:expr, $($arg:tt)+) => {
+        $crate::ResultExt::with_context($result, || ::std::format!($($arg)+))
+    };
+}
+
+/// Asserts that each level of an error chain contains the expected string.
+///
+/// On mismatch, this panics with both the expected levels and the actual chain.
+/// See [`Error::matches_chain`].
+#[macro_export]
+macro_rules! assert_error_chain {
+    ($err:expr, [$($expected:expr),* $(,)?] $(,)?) => {{
+        let err: &$crate::Error = &$err;
+        let expected: &[&str] = &[$($expected),*];
+        if !err.matches_chain(expected) {
+            let expected = expected
+                .iter()
+                .enumerate()
+                .map(|(i, expected)| ::std::format!("{}: {expected}", i + 1))
+                .collect::<::std::vec::Vec<_>>()
+                .join("\n");
+            ::std::panic!(
+                "error chain does not match\nexpected:\n{}\nactual:\n{}",
+                expected,
+                err.display_chain()
+            );
+        }
+    }};
+}
+
+/// Asserts that a `Result<T, Error>` is an error whose chain contains a string.
+///
+/// The string may span several levels, as it is matched against the messages
+/// of [`Error::display_chain`] joined with `": "`. On mismatch, this panics with
+/// the full `Debug` output of the error, including span trace and backtraces.
+#[macro_export]
+macro_rules! assert_err_matches {
+    ($result:expr, $expected:expr $(,)?) => {{
+        let result: &::std::result::Result<_, $crate::Error> = &$result;
+        let expected: &str = $expected;
+        match result {
+            ::std::result::Result::Ok(_) => {
+                ::std::panic!("expected error containing {:?}, got Ok(..)", expected)
+            }
+            ::std::result::Result::Err(err) => {
+                let chain = err
+                    .chain()
+                    .map(|s| s.to_string())
+                    .collect::<::std::vec::Vec<_>>();
+                if !chain.join(": ").contains(expected) {
+                    ::std::panic!("error does not contain {:?}\nactual:\n{:?}", expected, err);
+                }
+            }
+        }
+    }};
+}
+
 pub trait ResultExt<T> {
     #[track_caller]
     fn context<C>(self, context: C) -> Result<T, Error>
@@ -32,6 +134,159 @@ pub trait ResultExt<T> {
     /// Quickly convert a std error into a `Error`, without having to write a `context` message.
     #[track_caller]
     fn e(self) -> Result<T, Error>;
+
+    /// Like [`ResultExt::context`], but borrows the static message instead of
+    /// copying it, so adding the context does not allocate for it.
+    #[track_caller]
+    fn context_static(self, context: &'static str) -> Result<T, Error>;
+
+    /// Like [`ResultExt::context`], but moves an owned message into the error
+    /// instead of copying it.
+    #[track_caller]
+    fn context_owned(self, message: String) -> Result<T, Error>
+    where
+        Self: Sized,
+    {
+        self.with_context(move || message)
+    }
+
+    /// Maps the success value with `f`, or adds `context` to the error.
+    #[track_caller]
+    fn map_context<U, F, C>(self, f: F, context: C) -> Result<U, Error>
+    where
+        Self: Sized,
+        F: FnOnce(T) -> U,
+        C: AsRef<str>,
+    {
+        self.context(context).map(f)
+    }
+
+    /// Like [`ResultExt::context`], additionally attaching an [`ErrorCode`].
+    #[track_caller]
+    fn context_code<C>(self, code: ErrorCode, context: C) -> Result<T, Error>
+    where
+        Self: Sized,
+        C: AsRef<str>,
+    {
+        self.context(context).map_err(|err| err.with_code(code))
+    }
+
+    /// Adds the position of a failing element as context, as `"item {index}"`.
+    ///
+    /// Useful when collecting results, e.g. in `.enumerate().map(..)`.
+    #[track_caller]
+    fn context_indexed(self, index: usize) -> Result<T, Error>
+    where
+        Self: Sized,
+    {
+        self.with_context(|| format!("item {index}"))
+    }
+
+    /// Returns the value, or logs the error with [`Error::emit`] and returns `default`.
+    ///
+    /// Useful for best-effort operations, which should not fail the caller.
+    #[track_caller]
+    fn unwrap_or_log(self, default: T) -> T
+    where
+        Self: Sized,
+    {
+        self.unwrap_or_else_log(|| default)
+    }
+
+    /// Like [`ResultExt::unwrap_or_log`], computing the default with `f`.
+    #[track_caller]
+    fn unwrap_or_else_log<F>(self, f: F) -> T
+    where
+        Self: Sized,
+        F: FnOnce() -> T,
+    {
+        match self.e() {
+            Ok(v) => v,
+            Err(err) => {
+                err.emit();
+                f()
+            }
+        }
+    }
+
+    /// Returns the value, or logs the error as a warning and returns `None`.
+    ///
+    /// Useful for tolerant pipelines, which skip failed items and continue.
+    #[track_caller]
+    fn warn_on_err(self) -> Option<T>
+    where
+        Self: Sized,
+    {
+        match self.e() {
+            Ok(v) => Some(v),
+            Err(err) => {
+                err.emit_warn();
+                None
+            }
+        }
+    }
+
+    /// Like [`ResultExt::warn_on_err`], adding `context` to the logged error.
+    #[track_caller]
+    fn warn_on_err_context<C>(self, context: C) -> Option<T>
+    where
+        Self: Sized,
+        C: AsRef<str>,
+    {
+        match self.context(context) {
+            Ok(v) => Some(v),
+            Err(err) => {
+                err.emit_warn();
+                None
+            }
+        }
+    }
+
+    /// Like [`ResultExt::context`], additionally attaching `source` as a further cause.
+    ///
+    /// The chain lists `context`, then the original error, then `source`, see
+    /// [`Error::and_then_cause`]. Useful if a second error is at hand, e.g. the
+    /// one which made a failed rollback necessary.
+    #[track_caller]
+    fn context_with_source<C, S>(self, context: C, source: S) -> Result<T, Error>
+    where
+        Self: Sized,
+        C: AsRef<str>,
+        S: snafu::Error + Send + Sync + 'static,
+    {
+        match self.e() {
+            Ok(v) => Ok(v),
+            Err(err) => {
+                let source = Err::<(), _>(source).e().unwrap_err();
+                Err(err.and_then_cause(source).context(context))
+            }
+        }
+    }
+
+    /// Like [`ResultExt::context`], but without capturing a span trace or backtrace.
+    ///
+    /// This makes wrapping cheap, at the cost of the link carrying no diagnostics
+    /// beyond its message and location. Use it where errors are usually discarded,
+    /// e.g. when probing.
+    ///
+    /// The registered context providers are not run, so the link records no
+    /// fields, no span field and no creation time.
+    #[track_caller]
+    fn context_light<C>(self, context: C) -> Result<T, Error>
+    where
+        Self: Sized,
+        C: AsRef<str>,
+    {
+        self.with_context_light(|| context.as_ref().into())
+    }
+
+    /// Like [`ResultExt::with_context`], see [`ResultExt::context_light`].
+    ///
+    /// As there, the link records no fields, no span field and no creation time.
+    #[track_caller]
+    fn with_context_light<F>(self, context: F) -> Result<T, Error>
+    where
+        F: FnOnce() -> String;
 }
 
 impl<T, E> ResultExt<T> for Result<T, E>
@@ -46,10 +301,27 @@ where
         match self {
             Ok(v) => Ok(v),
             Err(error) => Err(Error::Message {
-                message: Some(context.as_ref().into()),
+                message: Some(context.as_ref().to_owned().into()),
+                span_trace: GenerateImplicitData::generate(),
+                source: Box::new(error),
+                backtrace: capture_backtrace(),
+                location: Some(Location::caller()),
+                meta: Meta::capture(),
+            }),
+        }
+    }
+
+    #[track_caller]
+    fn context_static(self, context: &'static str) -> Result<T, Error> {
+        match self {
+            Ok(v) => Ok(v),
+            Err(error) => Err(Error::Message {
+                message: Some(Cow::Borrowed(context)),
                 span_trace: GenerateImplicitData::generate(),
                 source: Box::new(error),
-                backtrace: GenerateImplicitData::generate(),
+                backtrace: capture_backtrace(),
+                location: Some(Location::caller()),
+                meta: Meta::capture(),
             }),
         }
     }
@@ -62,7 +334,9 @@ where
                 message: None,
                 span_trace: GenerateImplicitData::generate(),
                 source: Box::new(error),
-                backtrace: GenerateImplicitData::generate(),
+                backtrace: capture_backtrace(),
+                location: Some(Location::caller()),
+                meta: Meta::capture(),
             }),
         }
     }
@@ -75,10 +349,30 @@ where
         match self {
             Ok(v) => Ok(v),
             Err(error) => Err(Error::Message {
-                message: Some(context()),
+                message: Some(context().into()),
                 span_trace: GenerateImplicitData::generate(),
                 source: Box::new(error),
-                backtrace: GenerateImplicitData::generate(),
+                backtrace: capture_backtrace(),
+                location: Some(Location::caller()),
+                meta: Meta::capture(),
+            }),
+        }
+    }
+
+    #[track_caller]
+    fn with_context_light<F>(self, context: F) -> Result<T, Error>
+    where
+        F: FnOnce() -> String,
+    {
+        match self {
+            Ok(v) => Ok(v),
+            Err(error) => Err(Error::Message {
+                message: Some(context().into()),
+                span_trace: SpanTrace::empty(),
+                source: Box::new(error),
+                backtrace: None,
+                location: Some(Location::caller()),
+                meta: None,
             }),
         }
     }
@@ -92,27 +386,43 @@ impl<T> ResultExt<T> for Result<T, Error> {
     {
         match self {
             Ok(v) => Ok(v),
-            Err(error) => Err(Error::Whatever {
-                message: Some(context.as_ref().into()),
-                span_trace: GenerateImplicitData::generate(),
-                source: Some(Box::new(error)),
-                backtrace: GenerateImplicitData::generate(),
-            }),
+            Err(error) => {
+                warn_on_deep_chain(&error);
+                Err(Error::Whatever {
+                    message: Some(context.as_ref().to_owned().into()),
+                    span_trace: GenerateImplicitData::generate(),
+                    backtrace: wrapping_backtrace(&error),
+                    source: Some(Box::new(error)),
+                    location: Some(Location::caller()),
+                    meta: Meta::capture(),
+                })
+            }
         }
     }
 
     #[track_caller]
-    fn e(self) -> Result<T, Error> {
+    fn context_static(self, context: &'static str) -> Result<T, Error> {
         match self {
             Ok(v) => Ok(v),
-            Err(error) => Err(Error::Whatever {
-                message: None,
-                span_trace: GenerateImplicitData::generate(),
-                source: Some(Box::new(error)),
-                backtrace: GenerateImplicitData::generate(),
-            }),
+            Err(error) => {
+                warn_on_deep_chain(&error);
+                Err(Error::Whatever {
+                    message: Some(Cow::Borrowed(context)),
+                    span_trace: GenerateImplicitData::generate(),
+                    backtrace: wrapping_backtrace(&error),
+                    source: Some(Box::new(error)),
+                    location: Some(Location::caller()),
+                    meta: Meta::capture(),
+                })
+            }
         }
     }
+
+    /// Returns the error unchanged, as it already is an [`Error`].
+    fn e(self) -> Result<T, Error> {
+        self
+    }
+
     #[track_caller]
     fn with_context<F>(self, context: F) -> Result<T, Error>
     where
@@ -120,18 +430,133 @@ impl<T> ResultExt<T> for Result<T, Error> {
     {
         match self {
             Ok(v) => Ok(v),
-            Err(error) => Err(Error::Whatever {
-                message: Some(context()),
-                span_trace: GenerateImplicitData::generate(),
-                source: Some(Box::new(error)),
-                backtrace: GenerateImplicitData::generate(),
-            }),
+            Err(error) => {
+                warn_on_deep_chain(&error);
+                Err(Error::Whatever {
+                    message: Some(context().into()),
+                    span_trace: GenerateImplicitData::generate(),
+                    backtrace: wrapping_backtrace(&error),
+                    source: Some(Box::new(error)),
+                    location: Some(Location::caller()),
+                    meta: Meta::capture(),
+                })
+            }
+        }
+    }
+
+    #[track_caller]
+    fn with_context_light<F>(self, context: F) -> Result<T, Error>
+    where
+        F: FnOnce() -> String,
+    {
+        match self {
+            Ok(v) => Ok(v),
+            Err(error) => {
+                warn_on_deep_chain(&error);
+                Err(Error::Whatever {
+                    message: Some(context().into()),
+                    span_trace: SpanTrace::empty(),
+                    backtrace: None,
+                    source: Some(Box::new(error)),
+                    location: Some(Location::caller()),
+                    meta: None,
+                })
+            }
         }
     }
 }
 
+/// Captures a backtrace for a new link wrapping `source`.
+///
+/// With `N0_SNAFU_DEDUP_BACKTRACE=1` no backtrace is captured if the wrapped
+/// error already carries one.
+fn wrapping_backtrace(source: &Error) -> Option<snafu::Backtrace> {
+    static DEDUP: OnceLock<bool> = OnceLock::new();
+    let dedup =
+        *DEDUP.get_or_init(|| std::env::var("N0_SNAFU_DEDUP_BACKTRACE").is_ok_and(|v| v == "1"));
+    wrapping_backtrace_inner(source, dedup)
+}
+
+fn wrapping_backtrace_inner(source: &Error, dedup: bool) -> Option<snafu::Backtrace> {
+    if dedup && source.stack().iter().any(|(bt, _)| bt.is_some()) {
+        return None;
+    }
+    capture_backtrace()
+}
+
+/// Captures a backtrace, if enabled through `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE`.
+///
+/// Only the frame addresses are recorded. Symbols are resolved when the
+/// backtrace is printed, see [`Backtrace`], as most errors never are.
+///
+/// On wasm no backtrace is captured, as it would not contain any useful frames.
+/// Neither is one without the `backtrace` feature.
+fn capture_backtrace() -> Option<snafu::Backtrace> {
+    backtraces_enabled().then(snafu::Backtrace::new_unresolved)
+}
+
+/// Whether [`capture_backtrace`] captures anything.
+fn backtraces_enabled() -> bool {
+    if cfg!(target_family = "wasm") || cfg!(not(feature = "backtrace")) {
+        return false;
+    }
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    // same rule as snafu uses for its own backtraces
+    *ENABLED.get_or_init(|| {
+        std::env::var_os("RUST_LIB_BACKTRACE")
+            .or_else(|| std::env::var_os("RUST_BACKTRACE"))
+            .is_some_and(|v| v == "1")
+    })
+}
+
+/// Sources walked in a chain before it is assumed to loop, see [`Source::Cycle`].
+const MAX_CHAIN_LEN: usize = 1024;
+
+/// Counts `source` and the sources below it, taking `source` as the `i`th link.
+///
+/// Stops at the `limit`th link, without walking the rest of the chain.
+fn count_sources(source: Option<SourceWrapper<'_>>, i: usize, limit: usize) -> usize {
+    match source {
+        Some(s) if i < limit => 1 + count_sources(s.source(), i + 1, limit),
+        _ => 0,
+    }
+}
+
+static MAX_CONTEXT_DEPTH: AtomicUsize = AtomicUsize::new(128);
+
+/// Sets the number of links after which adding context to an [`Error`] logs a warning.
+///
+/// This only has an effect in debug builds, to catch errors that are accidentally
+/// wrapped over and over again, e.g. in a retry loop. Defaults to 128.
+pub fn set_max_context_depth(depth: usize) {
+    MAX_CONTEXT_DEPTH.store(depth, Ordering::Relaxed);
+}
+
+#[cfg(debug_assertions)]
+fn warn_on_deep_chain(error: &Error) {
+    let max = MAX_CONTEXT_DEPTH.load(Ordering::Relaxed);
+    // like `Error::depth`, but without walking further than needed
+    let depth = 1 + count_sources(error.skip_transparent().source(), 0, max);
+    // links added by `context` were already checked when they were added, so
+    // only warn once per chain, when crossing the threshold
+    let was = match error {
+        Error::Whatever {
+            message: Some(_),
+            source: Some(_),
+            ..
+        } => depth,
+        _ => 0,
+    };
+    if depth + 1 >= max && was < max {
+        tracing::warn!("error chain exceeds {max} links, is it wrapped in a loop?");
+    }
+}
+
+#[cfg(not(debug_assertions))]
+fn warn_on_deep_chain(_error: &Error) {}
+
 #[derive(Debug, Snafu)]
-#[snafu(display("Expected some, found none"))]
+#[snafu(display("{}", MessageStrings::current().none))]
 struct NoneError;
 
 impl<T> ResultExt<T> for Option<T> {
@@ -143,10 +568,27 @@ impl<T> ResultExt<T> for Option<T> {
         match self {
             Some(v) => Ok(v),
             None => Err(Error::Message {
-                message: Some(context.as_ref().into()),
+                message: Some(context.as_ref().to_owned().into()),
+                span_trace: GenerateImplicitData::generate(),
+                source: Box::new(NoneError),
+                backtrace: capture_backtrace(),
+                location: Some(Location::caller()),
+                meta: Meta::capture(),
+            }),
+        }
+    }
+
+    #[track_caller]
+    fn context_static(self, context: &'static str) -> Result<T, Error> {
+        match self {
+            Some(v) => Ok(v),
+            None => Err(Error::Message {
+                message: Some(Cow::Borrowed(context)),
                 span_trace: GenerateImplicitData::generate(),
                 source: Box::new(NoneError),
-                backtrace: GenerateImplicitData::generate(),
+                backtrace: capture_backtrace(),
+                location: Some(Location::caller()),
+                meta: Meta::capture(),
             }),
         }
     }
@@ -159,7 +601,9 @@ impl<T> ResultExt<T> for Option<T> {
                 message: None,
                 span_trace: GenerateImplicitData::generate(),
                 source: Box::new(NoneError),
-                backtrace: GenerateImplicitData::generate(),
+                backtrace: capture_backtrace(),
+                location: Some(Location::caller()),
+                meta: Meta::capture(),
             }),
         }
     }
@@ -172,612 +616,3675 @@ impl<T> ResultExt<T> for Option<T> {
         match self {
             Some(v) => Ok(v),
             None => Err(Error::Message {
-                message: Some(context()),
+                message: Some(context().into()),
                 span_trace: GenerateImplicitData::generate(),
                 source: Box::new(NoneError),
-                backtrace: GenerateImplicitData::generate(),
+                backtrace: capture_backtrace(),
+                location: Some(Location::caller()),
+                meta: Meta::capture(),
+            }),
+        }
+    }
+
+    #[track_caller]
+    fn with_context_light<F>(self, context: F) -> Result<T, Error>
+    where
+        F: FnOnce() -> String,
+    {
+        match self {
+            Some(v) => Ok(v),
+            None => Err(Error::Message {
+                message: Some(context().into()),
+                span_trace: SpanTrace::empty(),
+                source: Box::new(NoneError),
+                backtrace: None,
+                location: Some(Location::caller()),
+                meta: None,
             }),
         }
     }
 }
 
-// Trait safe version
-pub trait Formatted: snafu::Error {
-    /// Returns a [`Backtrace`][] that may be printed.
-    fn backtrace(&self) -> Option<Backtrace<'_>>;
+/// Adds context to errors which only implement [`std::error::Error`] behind a box.
+pub trait StdResultExt<T> {
+    /// Like [`ResultExt::context`], for `Box<dyn std::error::Error + Send + Sync>` errors.
+    #[track_caller]
+    fn context_std<C>(self, context: C) -> Result<T, Error>
+    where
+        C: AsRef<str>;
 }
 
-impl<T: snafu::Error + snafu::ErrorCompat> Formatted for T {
-    fn backtrace(&self) -> Option<Backtrace<'_>> {
-        snafu::ErrorCompat::backtrace(self).map(Backtrace::Crate)
+impl<T> StdResultExt<T> for Result<T, Box<dyn std::error::Error + Send + Sync + 'static>> {
+    #[track_caller]
+    fn context_std<C>(self, context: C) -> Result<T, Error>
+    where
+        C: AsRef<str>,
+    {
+        match self {
+            Ok(v) => Ok(v),
+            Err(error) => Err(Error::Message {
+                message: Some(context.as_ref().to_owned().into()),
+                span_trace: GenerateImplicitData::generate(),
+                source: Box::new(BoxedError(error)),
+                backtrace: capture_backtrace(),
+                location: Some(Location::caller()),
+                meta: Meta::capture(),
+            }),
+        }
     }
 }
 
-pub enum Error {
-    Source {
-        source: Box<dyn Formatted + Sync + Send + 'static>,
-        span_trace: SpanTrace,
-        backtrace: Option<snafu::Backtrace>,
-    },
-    Message {
-        message: Option<String>,
-        span_trace: SpanTrace,
-        source: Box<dyn snafu::Error + Sync + Send + 'static>,
-        backtrace: Option<snafu::Backtrace>,
-    },
-    Anyhow {
-        source: anyhow::Error,
-        span_trace: SpanTrace,
-        backtrace: Option<snafu::Backtrace>,
-    },
-    Whatever {
-        message: Option<String>,
-        span_trace: SpanTrace,
-        source: Option<Box<Error>>,
-        backtrace: Option<snafu::Backtrace>,
-    },
+/// Adds context to errors implementing [`snafu::ErrorCompat`], keeping their backtraces.
+///
+/// [`ResultExt::context`] only sees a plain [`snafu::Error`], so a backtrace
+/// captured by the source itself does not show up in [`Error::stack`].
+pub trait FormattedResultExt<T> {
+    /// Like [`ResultExt::context`], storing the source as [`Error::Source`].
+    #[track_caller]
+    fn context_formatted<C>(self, context: C) -> Result<T, Error>
+    where
+        C: AsRef<str>;
 }
 
-impl<E1: Formatted + Send + Sync + 'static> From<E1> for Error {
-    fn from(value: E1) -> Self {
-        Self::Source {
-            source: Box::new(value),
-            span_trace: GenerateImplicitData::generate(),
-            backtrace: GenerateImplicitData::generate(),
-        }
+impl<T, E> FormattedResultExt<T> for Result<T, E>
+where
+    E: snafu::Error + snafu::ErrorCompat + Send + Sync + 'static,
+{
+    #[track_caller]
+    fn context_formatted<C>(self, context: C) -> Result<T, Error>
+    where
+        C: AsRef<str>,
+    {
+        self.map_err(Error::from).context(context)
     }
 }
 
-impl FromString for Error {
-    type Source = Error;
+/// Adds context to an error only if it matches a predicate.
+pub trait ContextIfExt<T, E> {
+    /// Like [`ResultExt::context`], but only if `pred` returns `true` for the error.
+    ///
+    /// Otherwise the error is converted with `From`, without an additional link.
+    #[track_caller]
+    fn context_if<P, C>(self, pred: P, context: C) -> Result<T, Error>
+    where
+        P: FnOnce(&E) -> bool,
+        C: AsRef<str>;
+}
 
-    fn without_source(message: String) -> Self {
-        Self::Whatever {
-            message: Some(message),
-            span_trace: GenerateImplicitData::generate(),
-            backtrace: GenerateImplicitData::generate(),
-            source: None,
+impl<T, E> ContextIfExt<T, E> for Result<T, E>
+where
+    E: snafu::Error + snafu::ErrorCompat + Send + Sync + 'static,
+{
+    #[track_caller]
+    fn context_if<P, C>(self, pred: P, context: C) -> Result<T, Error>
+    where
+        P: FnOnce(&E) -> bool,
+        C: AsRef<str>,
+    {
+        match self {
+            Ok(v) => Ok(v),
+            Err(error) if pred(&error) => Err(error).context(context),
+            Err(error) => Err(Error::from(error)),
         }
     }
+}
 
-    fn with_source(source: Error, message: String) -> Self {
-        Self::Whatever {
-            message: Some(message),
-            span_trace: GenerateImplicitData::generate(),
-            backtrace: GenerateImplicitData::generate(),
-            source: Some(Box::new(source)),
+/// Adds context to nested results, e.g. `Result<Result<T, E1>, E2>`.
+pub trait FlattenResultExt<T> {
+    /// Flattens both layers into one [`Error`] with `context` on top.
+    ///
+    /// Whichever error occurred, the outer or the inner one, becomes the
+    /// source of the added context.
+    #[track_caller]
+    fn flatten_context<C>(self, context: C) -> Result<T, Error>
+    where
+        C: AsRef<str>;
+}
+
+impl<T, E1, E2> FlattenResultExt<T> for Result<Result<T, E1>, E2>
+where
+    E1: Into<Error>,
+    E2: Into<Error>,
+{
+    #[track_caller]
+    fn flatten_context<C>(self, context: C) -> Result<T, Error>
+    where
+        C: AsRef<str>,
+    {
+        let res = match self {
+            Ok(Ok(v)) => Ok(v),
+            Ok(Err(inner)) => Err(inner.into()),
+            Err(outer) => Err(outer.into()),
+        };
+        res.context(context)
+    }
+}
+
+/// Adds context to errors which only implement [`Display`](std::fmt::Display).
+pub trait DisplayResultExt<T> {
+    /// Formats the error into the message as `"{context}: {error}"`.
+    ///
+    /// The resulting [`Error`] has no source, as the original error is not a
+    /// [`std::error::Error`].
+    #[track_caller]
+    fn whatever_context<C>(self, context: C) -> Result<T, Error>
+    where
+        C: AsRef<str>;
+}
+
+impl<T, E: std::fmt::Display> DisplayResultExt<T> for Result<T, E> {
+    #[track_caller]
+    fn whatever_context<C>(self, context: C) -> Result<T, Error>
+    where
+        C: AsRef<str>,
+    {
+        match self {
+            Ok(v) => Ok(v),
+            Err(error) => Err(Error::Whatever {
+                message: Some(format!("{}: {error}", context.as_ref()).into()),
+                span_trace: GenerateImplicitData::generate(),
+                source: None,
+                backtrace: capture_backtrace(),
+                location: Some(Location::caller()),
+                meta: Meta::capture(),
+            }),
         }
     }
 }
 
-impl std::fmt::Debug for Error {
+/// Transparent wrapper, as `Box<dyn Error>` does not implement `Error` itself.
+#[derive(Debug)]
+struct BoxedError(Box<dyn std::error::Error + Send + Sync + 'static>);
+
+impl std::fmt::Display for BoxedError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let verb = Verbosity::from_env();
-
-        let filters = [
-            "<n0_snafu::testerror::Error",
-            "n0_snafu::testerror::Error::anyhow",
-            "<core::pin::Pin<P> as core::future::future::Future>::poll",
-            "<core::result::Result<T,F> as core::ops::try_trait::FromResidual<core::result::Result<core::convert::Infallible,E>>>::from_residual",
-        ];
-
-        let mut printer =
-            color_backtrace::BacktracePrinter::new().add_frame_filter(Box::new(move |frames| {
-                frames.retain(|frame| {
-                    frame
-                        .name
-                        .as_ref()
-                        .map(|name| {
-                            for f in &filters {
-                                if name.starts_with(f) {
-                                    return false;
-                                }
-                            }
-                            true
-                        })
-                        .unwrap_or(true)
-                })
-            }));
+        self.0.fmt(f)
+    }
+}
 
-        if verb != Verbosity::Full {
-            printer = printer.add_frame_filter(Box::new(|frames| {
-                frames.retain(|frame| !frame.is_dependency_code())
-            }));
-        }
+impl std::error::Error for BoxedError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
 
-        let stack = self.stack();
+/// A link of an [`Error`] converted into a `Box<dyn std::error::Error>`.
+///
+/// Only the messages are kept, as not every link of an [`Error`] is a
+/// `std::error::Error` itself.
+pub(crate) struct ChainLink {
+    message: String,
+    source: Option<Box<ChainLink>>,
+}
 
-        write!(f, "{self:#}")?;
+impl ChainLink {
+    pub(crate) fn new(err: &Error) -> Self {
+        err.chain_messages()
+            .into_iter()
+            .rev()
+            .fold(None, |source, message| {
+                Some(ChainLink {
+                    message,
+                    source: source.map(Box::new),
+                })
+            })
+            .unwrap_or_else(|| ChainLink {
+                message: err.to_string(),
+                source: None,
+            })
+    }
+}
 
-        // Span Trace
-        if self.span_trace().status() == SpanTraceStatus::CAPTURED {
-            writeln!(f, "Span trace:")?;
-            writeln!(f, "{}\n", self.span_trace())?;
-        }
+impl std::fmt::Display for ChainLink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
 
-        // Backtrace
-        for (bt, _source) in stack.into_iter() {
-            if let Some(bt) = bt {
-                let s = printer.format_trace_to_string(&bt).unwrap();
-                writeln!(f, "\n{s}")?;
-            }
+/// Same layout as the alternate `Display` of [`Error`].
+impl std::fmt::Debug for ChainLink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)?;
+        let mut source = self.source.as_deref();
+        let mut i = 0;
+        while let Some(link) = source {
+            write!(f, "\n  {i}: {}", link.message)?;
+            source = link.source.as_deref();
+            i += 1;
         }
         Ok(())
     }
 }
 
-impl Error {
-    pub fn span_trace(&self) -> &SpanTrace {
-        match self {
-            Self::Source { span_trace, .. } => span_trace,
-            Self::Message { span_trace, .. } => span_trace,
-            Self::Anyhow { span_trace, .. } => span_trace,
-            Self::Whatever { span_trace, .. } => span_trace,
-        }
+impl std::error::Error for ChainLink {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source
+            .as_deref()
+            .map(|link| link as &(dyn std::error::Error + 'static))
     }
+}
 
-    pub fn backtrace(&self) -> Option<Backtrace<'_>> {
-        let backtrace = match self {
-            Self::Source { backtrace, .. } => backtrace.as_ref(),
-            Self::Message { backtrace, .. } => backtrace.as_ref(),
-            Self::Anyhow { backtrace, .. } => backtrace.as_ref(),
-            Self::Whatever { backtrace, .. } => backtrace.as_ref(),
-        };
-        backtrace.map(Backtrace::Crate)
+/// Allows `?` in functions returning a boxed `std::error::Error`.
+///
+/// The messages of the chain are kept, backtraces and span traces are dropped.
+impl From<Error> for Box<dyn std::error::Error + Send + Sync + 'static> {
+    fn from(err: Error) -> Self {
+        Box::new(ChainLink::new(&err))
     }
+}
 
-    pub fn anyhow(err: anyhow::Error) -> Self {
-        Self::Anyhow {
-            source: err,
-            span_trace: GenerateImplicitData::generate(),
-            backtrace: GenerateImplicitData::generate(),
-        }
+impl From<Error> for Box<dyn std::error::Error + 'static> {
+    fn from(err: Error) -> Self {
+        Box::new(ChainLink::new(&err))
     }
+}
 
-    pub fn stack(&self) -> Vec<(Option<Backtrace<'_>>, Source<'_>)> {
-        let mut traces = Vec::new();
-        match self {
-            Self::Source {
-                source, backtrace, ..
-            } => {
-                // current trace
-                traces.push((backtrace.as_ref().map(Backtrace::Crate), Source::Root));
-                traces.push((source.backtrace(), Source::Formatted(source.as_ref())));
+/// Uses the code set with [`Error::with_exit_code`], or else `ExitCode::FAILURE`.
+///
+/// E.g. `fn main() -> ExitCode` can print the error and return this, to exit
+/// with a specific code on stable Rust.
+impl From<&Error> for std::process::ExitCode {
+    fn from(err: &Error) -> Self {
+        err.exit_code()
+            .map_or(std::process::ExitCode::FAILURE, Into::into)
+    }
+}
 
-                // collect the traces from our sources
-                let mut source = source.source();
+// Trait safe version
+pub trait Formatted: snafu::Error {
+    /// Returns a [`Backtrace`][] that may be printed.
+    fn backtrace(&self) -> Option<Backtrace<'_>>;
 
-                while let Some(s) = source {
-                    if let Some(this) = s.downcast_ref::<&dyn Formatted>() {
-                        traces.push((this.backtrace(), Source::Formatted(*this)));
-                    } else {
-                        traces.push((None, Source::SnafuError(s)));
-                    }
-                    source = s.source();
-                }
-            }
-            Self::Message {
-                source, backtrace, ..
-            } => {
-                // current trace
-                traces.push((backtrace.as_ref().map(Backtrace::Crate), Source::Root));
+    /// Returns this error as a plain error, e.g. to downcast it.
+    fn as_error_source(&self) -> &(dyn snafu::Error + 'static)
+    where
+        Self: 'static;
+}
 
-                // collect the traces from our sources
-                let mut source: Option<&(dyn snafu::Error + 'static)> = Some(source.as_ref());
+impl<T: snafu::Error + snafu::ErrorCompat> Formatted for T {
+    fn backtrace(&self) -> Option<Backtrace<'_>> {
+        // without the `backtrace` feature, backtraces captured by snafu are ignored
+        snafu::ErrorCompat::backtrace(self)
+            .filter(|_| cfg!(feature = "backtrace"))
+            .map(Backtrace::Crate)
+    }
 
-                while let Some(s) = source {
-                    if let Some(this) = s.downcast_ref::<&dyn Formatted>() {
-                        traces.push((this.backtrace(), Source::Formatted(*this)));
-                    } else {
-                        traces.push((None, Source::SnafuError(s)));
-                    }
-                    source = s.source();
-                }
-            }
-            Self::Anyhow {
-                source, backtrace, ..
-            } => {
-                // current trace
-                traces.push((backtrace.as_ref().map(Backtrace::Crate), Source::Root));
+    fn as_error_source(&self) -> &(dyn snafu::Error + 'static)
+    where
+        Self: 'static,
+    {
+        self
+    }
+}
 
-                traces.push((
-                    Some(Backtrace::Std(source.backtrace())),
-                    Source::Anyhow(source),
-                ));
+/// How an [`Error`] was constructed, see [`Error::kind`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// Context added to another error, e.g. with [`ResultExt::context`].
+    Context,
+    /// An [`anyhow::Error`], see [`Error::anyhow`].
+    Anyhow,
+    /// A message, optionally on top of another [`Error`], e.g. from [`format_err!`].
+    Adhoc,
+    /// Another error converted with `From`, e.g. by `?`.
+    Wrapped,
+    /// Several independent errors, see [`Error::multiple`].
+    Multiple,
+}
 
-                for s in source.chain().skip(1) {
-                    if let Some(this) = s.downcast_ref::<&dyn Formatted>() {
-                        traces.push((this.backtrace(), Source::Formatted(*this)));
-                    } else {
-                        traces.push((None, Source::SnafuError(s)));
-                    }
-                }
-            }
-            Self::Whatever {
-                source, backtrace, ..
-            } => {
-                // current trace
-                traces.push((backtrace.as_ref().map(Backtrace::Crate), Source::Root));
+/// A stable category for an [`Error`], e.g. to map failures to protocol status codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ErrorCode {
+    code: &'static str,
+    retryable: bool,
+}
 
-                // collect the traces from our sources
-                if let Some(s) = source.as_deref() {
-                    traces.push((s.backtrace(), Source::Error(s)));
-                    s.stack_inner(&mut traces);
-                }
-            }
+impl ErrorCode {
+    pub const fn new(code: &'static str) -> Self {
+        Self {
+            code,
+            retryable: false,
         }
-
-        traces
     }
 
-    fn stack_inner<'a>(&'a self, traces: &mut Vec<(Option<Backtrace<'a>>, Source<'a>)>) {
-        match self {
-            Self::Source { source, .. } => {
-                traces.push((source.backtrace(), Source::Formatted(source.as_ref())));
+    /// A code for transient failures, see [`Error::is_retryable`].
+    pub const fn retryable(code: &'static str) -> Self {
+        Self {
+            code,
+            retryable: true,
+        }
+    }
 
-                // collect the traces from our sources
-                let mut source = source.source();
+    pub const fn as_str(&self) -> &'static str {
+        self.code
+    }
 
-                while let Some(s) = source {
-                    if let Some(this) = s.downcast_ref::<&dyn Formatted>() {
-                        traces.push((this.backtrace(), Source::Formatted(*this)));
-                    } else {
-                        traces.push((None, Source::SnafuError(s)));
-                    }
-                    source = s.source();
-                }
-            }
-            Self::Message { source, .. } => {
-                // collect the traces from our sources
-                let mut source: Option<&(dyn snafu::Error + 'static)> = Some(source.as_ref());
+    pub const fn is_retryable(&self) -> bool {
+        self.retryable
+    }
+}
 
-                while let Some(s) = source {
-                    if let Some(this) = s.downcast_ref::<&dyn Formatted>() {
-                        traces.push((this.backtrace(), Source::Formatted(*this)));
-                    } else {
-                        traces.push((None, Source::SnafuError(s)));
-                    }
-                    source = s.source();
-                }
-            }
-            Self::Anyhow { source, .. } => {
-                traces.push((
-                    Some(Backtrace::Std(source.backtrace())),
-                    Source::Anyhow(source),
-                ));
-
-                for s in source.chain().skip(1) {
-                    if let Some(this) = s.downcast_ref::<&dyn Formatted>() {
-                        traces.push((this.backtrace(), Source::Formatted(*this)));
-                    } else {
-                        traces.push((None, Source::SnafuError(s)));
-                    }
-                }
-            }
-            Self::Whatever { source, .. } => {
-                // collect the traces from our sources
-                if let Some(s) = source.as_deref() {
-                    traces.push((s.backtrace(), Source::Error(s)));
-                    let stack = s.stack();
-                    traces.extend(stack);
-                }
-            }
-        }
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.code)
     }
 }
 
-#[derive(Clone, Debug)]
-pub enum Backtrace<'a> {
-    Crate(&'a snafu::Backtrace),
-    Std(&'a std::backtrace::Backtrace),
+/// Optional metadata of a link, boxed as it is rarely set.
+#[derive(Debug, Clone, Default)]
+pub struct Meta {
+    code: Option<ErrorCode>,
+    exit_code: Option<u8>,
+    seq: Option<u64>,
+    fields: Vec<(String, String)>,
+    created_at: Option<SystemTime>,
+    thread_name: Option<String>,
+    help: Vec<String>,
+    span_field: Option<(&'static str, String)>,
+    /// Span trace of an error rebuilt from a snapshot, only for display.
+    pub(crate) remote_span_trace: Option<String>,
+    /// Backtrace frames of an error rebuilt from a snapshot, only for display.
+    #[cfg(feature = "backtrace")]
+    pub(crate) remote_backtrace: Option<Vec<String>>,
+    /// The backtrace of the link, once resolved for printing.
+    resolved: ResolvedBacktrace,
 }
 
-impl color_backtrace::Backtrace for Backtrace<'_> {
-    fn frames(&self) -> Vec<color_backtrace::Frame> {
-        match self {
-            Self::Crate(bt) => color_backtrace::Backtrace::frames(*bt),
-            Self::Std(bt) => {
-                // no comment, things are sad in std land
-                let parsed_bt = btparse::deserialize(bt).expect("failed to parse stacks");
-                color_backtrace::Backtrace::frames(&parsed_bt)
-            }
+static CAPTURE_CREATION: AtomicBool = AtomicBool::new(false);
+
+/// Enables recording when and on which thread each [`Error`] is created.
+///
+/// This is disabled by default, which also keeps the output deterministic, e.g.
+/// in tests. See [`Error::created_at`] and [`Error::thread_name`].
+pub fn set_capture_creation(enabled: bool) {
+    CAPTURE_CREATION.store(enabled, Ordering::Relaxed);
+}
+
+type ContextProvider = dyn Fn() -> Vec<(String, String)> + Send + Sync + 'static;
+
+static CONTEXT_PROVIDERS: RwLock<Vec<Arc<ContextProvider>>> = RwLock::new(Vec::new());
+
+/// Registers a provider of fields which are added to every newly constructed [`Error`].
+///
+/// Useful for cross-cutting context, e.g. the id of the current tenant. The
+/// fields are available through [`Error::fields`].
+pub fn register_context_provider(
+    provider: impl Fn() -> Vec<(String, String)> + Send + Sync + 'static,
+) {
+    let mut providers = CONTEXT_PROVIDERS.write().unwrap_or_else(|e| e.into_inner());
+    providers.push(Arc::new(provider));
+}
+
+impl Meta {
+    /// Runs the registered context providers and records the creation and the
+    /// span field shown by `Display`, if enabled.
+    ///
+    /// With backtraces enabled this is always allocated, to cache the resolved
+    /// backtrace in.
+    fn capture() -> Option<Box<Self>> {
+        let providers = CONTEXT_PROVIDERS
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        let creation = CAPTURE_CREATION.load(Ordering::Relaxed);
+        let span_field = crate::spantrace::display_span_field();
+        if providers.is_empty() && !creation && span_field.is_none() && !backtraces_enabled() {
+            return None;
+        }
+
+        let mut meta = Self {
+            fields: providers.iter().flat_map(|provider| provider()).collect(),
+            span_field,
+            ..Default::default()
+        };
+        if creation {
+            let thread = std::thread::current();
+            meta.created_at = Some(SystemTime::now());
+            meta.thread_name = Some(match thread.name() {
+                Some(name) => name.to_string(),
+                None => format!("{:?}", thread.id()),
+            });
         }
+        Some(Box::new(meta))
     }
 }
 
-pub enum Source<'a> {
-    Root,
-    Formatted(&'a dyn Formatted),
-    SnafuError(&'a dyn snafu::Error),
-    Error(&'a Error),
-    Anyhow(&'a anyhow::Error),
+/// The error type of this crate.
+///
+/// New variants may be added, so prefer [`Error::kind`] over matching on them.
+/// The variants may also gain fields, so they can only be built with the
+/// constructors of this crate, e.g. [`Error::builder`] or [`format_err!`].
+#[non_exhaustive]
+pub enum Error {
+    #[non_exhaustive]
+    Source {
+        source: Box<dyn Formatted + Sync + Send + 'static>,
+        span_trace: SpanTrace,
+        backtrace: Option<snafu::Backtrace>,
+        /// Where the error was converted, e.g. the `?` returning it.
+        location: Option<&'static Location<'static>>,
+        meta: Option<Box<Meta>>,
+    },
+    #[non_exhaustive]
+    Message {
+        message: Option<Cow<'static, str>>,
+        span_trace: SpanTrace,
+        source: Box<dyn snafu::Error + Sync + Send + 'static>,
+        backtrace: Option<snafu::Backtrace>,
+        location: Option<&'static Location<'static>>,
+        meta: Option<Box<Meta>>,
+    },
+    #[non_exhaustive]
+    Anyhow {
+        source: anyhow::Error,
+        span_trace: SpanTrace,
+        backtrace: Option<snafu::Backtrace>,
+        meta: Option<Box<Meta>>,
+    },
+    #[non_exhaustive]
+    Whatever {
+        message: Option<Cow<'static, str>>,
+        span_trace: SpanTrace,
+        source: Option<Box<Error>>,
+        backtrace: Option<snafu::Backtrace>,
+        location: Option<&'static Location<'static>>,
+        meta: Option<Box<Meta>>,
+    },
+    /// Several independent errors, e.g. collected by [`try_collect_errors`].
+    #[non_exhaustive]
+    Multiple {
+        sources: Vec<Error>,
+        span_trace: SpanTrace,
+        backtrace: Option<snafu::Backtrace>,
+        meta: Option<Box<Meta>>,
+    },
 }
 
-impl core::fmt::Display for Source<'_> {
-    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
-        match self {
-            Self::Root => write!(f, "Root"),
-            Self::Formatted(e) => e.fmt(f),
-            Self::Error(e) => e.fmt(f),
-            Self::SnafuError(e) => e.fmt(f),
-            Self::Anyhow(e) => e.fmt(f),
+/// Runs through all results, collecting either every value or every error.
+///
+/// Unlike collecting into a `Result<Vec<T>>`, this does not stop at the first
+/// error. All errors are returned together as an [`Error::Multiple`].
+pub fn try_collect_errors<T>(iter: impl IntoIterator<Item = Result<T>>) -> Result<Vec<T>> {
+    let mut values = Vec::new();
+    let mut errors = Vec::new();
+    for res in iter {
+        match res {
+            Ok(value) => values.push(value),
+            Err(err) => errors.push(err),
         }
     }
+    if errors.is_empty() {
+        Ok(values)
+    } else {
+        Err(Error::multiple(errors))
+    }
 }
 
-impl snafu::ErrorCompat for Error {
-    fn backtrace(&self) -> Option<&snafu::Backtrace> {
-        self.stack().last().and_then(|(bt, _)| match *bt {
-            Some(Backtrace::Crate(bt)) => Some(bt),
-            _ => None,
-        })
+impl<E1: Formatted + Send + Sync + 'static> From<E1> for Error {
+    #[track_caller]
+    fn from(value: E1) -> Self {
+        Self::Source {
+            source: Box::new(value),
+            span_trace: GenerateImplicitData::generate(),
+            backtrace: capture_backtrace(),
+            location: Some(Location::caller()),
+            meta: Meta::capture(),
+        }
     }
 }
 
-trait ErrorSource<'a>: std::fmt::Display + std::fmt::Debug {
-    fn source(&'a self) -> Option<SourceWrapper<'a>>;
+/// Combines the errors into an [`Error::Multiple`].
+///
+/// A single error is returned as is, without an aggregate around it. No errors
+/// at all result in an [`Error::Whatever`] with the message `no errors`, as
+/// there is nothing to combine.
+///
+/// `From<Vec<Error>>` cannot be implemented next to the blanket `From` impl
+/// above, so collect the vec instead: `errors.into_iter().collect::<Error>()`.
+impl FromIterator<Error> for Error {
+    fn from_iter<I: IntoIterator<Item = Error>>(iter: I) -> Self {
+        let mut errors: Vec<_> = iter.into_iter().collect();
+        match errors.len() {
+            0 => Self::without_source("no errors".to_string()),
+            1 => errors.pop().expect("checked length"),
+            _ => Self::multiple(errors),
+        }
+    }
 }
 
-impl<'a> ErrorSource<'a> for Error {
-    fn source(&'a self) -> Option<SourceWrapper<'a>> {
-        match self {
-            Error::Source { source, .. } => source.source().map(SourceWrapper::Std),
-            Error::Anyhow { source, .. } => source.source().map(SourceWrapper::Std),
-            Error::Message { ref source, .. } => Some(SourceWrapper::Box(source)),
-            Error::Whatever { ref source, .. } => source.as_ref().map(|s| SourceWrapper::Crate(s)),
-        }
+/// Builds an [`Error::Source`] with explicit control over its backtrace and span trace.
+///
+/// Created by [`Error::builder`]. Anything not set is captured on [`build`](Self::build),
+/// just like when converting with `From`.
+#[derive(Default)]
+pub struct ErrorBuilder {
+    source: Option<Box<dyn Formatted + Sync + Send + 'static>>,
+    span_trace: Option<SpanTrace>,
+    backtrace: Option<Option<snafu::Backtrace>>,
+}
+
+impl std::fmt::Debug for ErrorBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ErrorBuilder")
+            .field("source", &self.source.as_ref().map(|s| s.to_string()))
+            .field("span_trace", &self.span_trace)
+            .field("backtrace", &self.backtrace)
+            .finish()
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-enum SourceWrapper<'a> {
-    Std(&'a dyn std::error::Error),
-    #[allow(clippy::borrowed_box)]
-    Box(&'a Box<dyn snafu::Error + Sync + Send + 'static>),
-    Crate(&'a Error),
+impl ErrorBuilder {
+    /// Sets the wrapped error.
+    pub fn source(mut self, source: impl Formatted + Send + Sync + 'static) -> Self {
+        self.source = Some(Box::new(source));
+        self
+    }
+
+    /// Sets the backtrace, `None` stores the error without one.
+    pub fn backtrace(mut self, backtrace: Option<snafu::Backtrace>) -> Self {
+        self.backtrace = Some(backtrace);
+        self
+    }
+
+    /// Sets the span trace.
+    pub fn span_trace(mut self, span_trace: SpanTrace) -> Self {
+        self.span_trace = Some(span_trace);
+        self
+    }
+
+    /// Builds the error.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no source was set.
+    pub fn build(self) -> Error {
+        Error::Source {
+            source: self
+                .source
+                .expect("ErrorBuilder::build called without a source"),
+            span_trace: self
+                .span_trace
+                .unwrap_or_else(GenerateImplicitData::generate),
+            backtrace: self.backtrace.unwrap_or_else(capture_backtrace),
+            location: None,
+            meta: Meta::capture(),
+        }
+    }
+}
+
+impl FromString for Error {
+    type Source = Error;
+
+    #[track_caller]
+    fn without_source(message: String) -> Self {
+        Self::Whatever {
+            message: Some(message.into()),
+            span_trace: GenerateImplicitData::generate(),
+            backtrace: capture_backtrace(),
+            source: None,
+            location: Some(Location::caller()),
+            meta: Meta::capture(),
+        }
+    }
+
+    #[track_caller]
+    fn with_source(source: Error, message: String) -> Self {
+        Self::Whatever {
+            message: Some(message.into()),
+            span_trace: GenerateImplicitData::generate(),
+            backtrace: capture_backtrace(),
+            source: Some(Box::new(source)),
+            location: Some(Location::caller()),
+            meta: Meta::capture(),
+        }
+    }
+}
+
+impl std::fmt::Debug for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_debug(f, &FormatOptions::current())
+    }
+}
+
+/// Formats an [`Error`] like its `Debug` impl, but with the given options.
+struct WithOptions<'a>(&'a Error, &'a FormatOptions);
+
+impl std::fmt::Debug for WithOptions<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt_debug(f, self.1)
+    }
+}
+
+impl Error {
+    /// Renders the same output as `Debug`, but never with colors.
+    ///
+    /// Useful for snapshot tests. Backtraces still depend on the build, so
+    /// errors compared this way should be built without one, e.g. with
+    /// [`Error::builder`].
+    pub fn to_plain_string(&self) -> String {
+        format!("{:?}", WithOptions(self, &FormatOptions::plain()))
+    }
+
+    fn fmt_debug(&self, f: &mut std::fmt::Formatter<'_>, opts: &FormatOptions) -> std::fmt::Result {
+        let strings = MessageStrings::current();
+
+        let style = TreeStyle {
+            colors: opts.colors(),
+            depth: 0,
+            max_message_len: opts.max_message_len,
+            max_sources: opts.max_sources,
+        };
+        self.fmt_span_field(f)?;
+        write!(f, "{:#}", NoPrefix(self, style))?;
+
+        for help in self.all_help() {
+            if opts.colors() {
+                write!(f, "\n\x1b[36mhelp: {help}\x1b[0m")?;
+            } else {
+                write!(f, "\nhelp: {help}")?;
+            }
+        }
+
+        if opts.chain_only() {
+            return Ok(());
+        }
+
+        for (key, value) in self.all_fields() {
+            write!(f, "\n  {key} = {value}")?;
+        }
+
+        let deterministic = opts.deterministic();
+
+        if let Some(location) = self.location() {
+            if deterministic {
+                write!(f, "\nat {}", location.file())?;
+            } else {
+                write!(f, "\nat {}:{}", location.file(), location.line())?;
+            }
+        }
+
+        if let Some(seq) = self.seq().filter(|_| !deterministic) {
+            write!(f, "\nseq {seq}")?;
+        }
+
+        if let (Some(created_at), Some(thread), false) =
+            (self.created_at(), self.thread_name(), deterministic)
+        {
+            let since_epoch = created_at.duration_since(UNIX_EPOCH).unwrap_or_default();
+            write!(
+                f,
+                "\ncreated at {}.{:03} on thread {thread}",
+                since_epoch.as_secs(),
+                since_epoch.subsec_millis()
+            )?;
+        }
+
+        // Span Trace
+        let spans = if self.span_trace().is_captured() {
+            Some(self.span_trace().format_relative())
+        } else {
+            self.meta().and_then(|m| m.remote_span_trace.clone())
+        };
+        if let Some(mut spans) = spans.filter(|spans| !spans.is_empty()) {
+            if deterministic {
+                // drop the `at file:line` of each span
+                spans = spans
+                    .lines()
+                    .filter(|line| !line.trim_start().starts_with("at "))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+            }
+            writeln!(f, "\n{}", strings.span_trace)?;
+            writeln!(f, "{spans}\n")?;
+        } else if self.span_trace().is_unsupported() && opts.verbosity() == Verbosity::Full {
+            writeln!(f, "\n{}", strings.span_trace_unsupported)?;
+        }
+
+        #[cfg(feature = "backtrace")]
+        self.fmt_backtraces(f, opts, &strings)?;
+        Ok(())
+    }
+
+    /// Writes the backtraces of the stack, for `Debug`.
+    #[cfg(feature = "backtrace")]
+    fn fmt_backtraces(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        opts: &FormatOptions,
+        strings: &MessageStrings,
+    ) -> std::fmt::Result {
+        if cfg!(target_family = "wasm") && opts.verbosity() != Verbosity::Minimal {
+            writeln!(f, "\n{}", strings.backtrace_unsupported)?;
+        }
+
+        if opts.deterministic() {
+            let remote = self.meta().is_some_and(|m| m.remote_backtrace.is_some());
+            if remote || !self.backtraces().is_empty() {
+                writeln!(f, "\n<backtrace omitted>")?;
+            }
+            return Ok(());
+        }
+
+        let printer = opts.printer();
+        let stack = self.stack();
+
+        // Backtraces, labeled with the source they belong to. Frames shared
+        // with the previously printed one are elided.
+        let mut previous = None;
+        let mut printed = Vec::new();
+        for (i, (bt, source)) in stack.into_iter().enumerate() {
+            let Some(bt) = bt else {
+                continue;
+            };
+            // resolve only once for both comparing and printing
+            let resolved = bt.resolve();
+            let bt = resolved.as_deref().map_or(bt, Backtrace::Crate);
+            let frames: Vec<_> = color_backtrace::Backtrace::frames(&bt)
+                .iter()
+                .map(frame_key)
+                .collect();
+            if frames.is_empty() {
+                continue;
+            }
+            let label = match source {
+                Source::Root => Source::Error(self),
+                source => source,
+            };
+            if previous.as_ref() == Some(&frames) {
+                writeln!(
+                    f,
+                    "\n{} {i}: {label} {}",
+                    strings.backtrace_for, strings.same_as_above
+                )?;
+            } else {
+                let s = match opts.compact_frames {
+                    Some(n) => opts.format_compact(&bt, n),
+                    None => opts.format_trace_after(&printer, &bt, &mut printed),
+                };
+                writeln!(f, "\n{} {i}: {label}\n{s}", strings.backtrace_for)?;
+                previous = Some(frames);
+            }
+        }
+
+        if let Some(frames) = self.meta().and_then(|m| m.remote_backtrace.as_ref()) {
+            writeln!(f, "\n{} 0: {}", strings.backtrace_for, Source::Error(self))?;
+            for (i, frame) in frames.iter().enumerate() {
+                writeln!(f, "{i:>2}: {frame}")?;
+            }
+        }
+        Ok(())
+    }
 }
 
-impl<'a> std::fmt::Display for SourceWrapper<'a> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            SourceWrapper::Std(error) => write!(f, "{error}"),
-            SourceWrapper::Crate(error) => match error {
-                Error::Message { message, .. } => {
-                    write!(f, "{}", message.as_deref().unwrap_or("Error"))
-                }
-                _ => write!(f, "{error}"),
-            },
-            SourceWrapper::Box(error) => write!(f, "{error}"),
-        }
+impl Error {
+    pub fn span_trace(&self) -> &SpanTrace {
+        match self {
+            Self::Source { span_trace, .. } => span_trace,
+            Self::Message { span_trace, .. } => span_trace,
+            Self::Anyhow { span_trace, .. } => span_trace,
+            Self::Whatever { span_trace, .. } => span_trace,
+            Self::Multiple { span_trace, .. } => span_trace,
+        }
+    }
+
+    /// Returns the first non-empty backtrace found walking from this error down to its root cause.
+    ///
+    /// Only backtraces captured by this crate or by snafu are considered, so this
+    /// always agrees with [`snafu::ErrorCompat::backtrace`]. The backtrace of an
+    /// [`anyhow::Error`] is part of [`Error::backtraces`].
+    pub fn backtrace(&self) -> Option<Backtrace<'_>> {
+        self.find_backtrace().map(|(bt, _)| bt)
+    }
+
+    /// Returns every non-empty backtrace in the chain, starting at this error.
+    ///
+    /// This includes the backtrace captured by an [`anyhow::Error`], if enabled.
+    pub fn backtraces(&self) -> Vec<Backtrace<'_>> {
+        self.stack()
+            .into_iter()
+            .filter_map(|(bt, _)| bt)
+            .filter(|bt| !bt.is_empty())
+            .collect()
+    }
+
+    /// The backtrace shared by [`Error::backtrace`] and [`snafu::ErrorCompat::backtrace`].
+    fn find_backtrace(&self) -> Option<(Backtrace<'_>, &snafu::Backtrace)> {
+        self.stack()
+            .into_iter()
+            .filter_map(|(bt, _)| bt)
+            .filter(|bt| !bt.is_empty())
+            .find_map(|bt| match bt {
+                Backtrace::Crate(inner) | Backtrace::Captured(inner, _) => Some((bt, inner)),
+                Backtrace::Std(_) => None,
+            })
+    }
+
+    /// The backtrace captured for this link itself.
+    fn own_backtrace(&self) -> Option<Backtrace<'_>> {
+        let backtrace = match self {
+            Self::Source { backtrace, .. } => backtrace.as_ref(),
+            Self::Message { backtrace, .. } => backtrace.as_ref(),
+            Self::Anyhow { backtrace, .. } => backtrace.as_ref(),
+            Self::Whatever { backtrace, .. } => backtrace.as_ref(),
+            Self::Multiple { backtrace, .. } => backtrace.as_ref(),
+        }?;
+        Some(match self.meta() {
+            Some(meta) => Backtrace::Captured(backtrace, &meta.resolved),
+            None => Backtrace::Crate(backtrace),
+        })
+    }
+
+    /// Returns the first error of type `T` in the chain of this error.
+    pub fn downcast_ref<T: snafu::Error + 'static>(&self) -> Option<&T> {
+        self.chain().find_map(|source| source.downcast_ref())
+    }
+
+    /// Renders the messages of the chain one per line, numbered from `1:`.
+    ///
+    /// Unlike `Debug`, this never includes span traces or backtraces. Repeated
+    /// messages of consecutive links are only shown once.
+    pub fn display_chain(&self) -> String {
+        self.chain_messages()
+            .iter()
+            .enumerate()
+            .map(|(i, message)| format!("{}: {message}", i + 1))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Whether each level of [`Error::display_chain`] contains the expected string.
+    ///
+    /// The chain must have exactly as many levels as strings are expected. See
+    /// [`assert_error_chain!`](crate::assert_error_chain) for use in tests.
+    pub fn matches_chain(&self, expected: &[&str]) -> bool {
+        let messages = self.chain_messages();
+        messages.len() == expected.len()
+            && messages
+                .iter()
+                .zip(expected)
+                .all(|(message, expected)| message.contains(expected))
+    }
+
+    fn chain_messages(&self) -> Vec<String> {
+        let mut messages: Vec<String> = Vec::new();
+        for source in self.chain() {
+            let message = source.to_string();
+            if messages.last() != Some(&message) {
+                messages.push(message);
+            }
+        }
+        messages
+    }
+
+    /// Logs this error as an error level `tracing` event.
+    ///
+    /// The event carries the error, its chain, its span trace and the location
+    /// `emit` was called at as fields.
+    #[track_caller]
+    pub fn emit(&self) {
+        let caller = Location::caller();
+        tracing::error!(
+            error = %self,
+            chain = ?self.display_chain(),
+            span_trace = %self.span_trace(),
+            caller = %caller,
+            "{self}"
+        );
+    }
+
+    /// Like [`Error::emit`], but as a warning.
+    #[track_caller]
+    pub(crate) fn emit_warn(&self) {
+        let caller = Location::caller();
+        tracing::warn!(
+            error = %self,
+            chain = ?self.display_chain(),
+            span_trace = %self.span_trace(),
+            caller = %caller,
+            "{self}"
+        );
+    }
+
+    /// Returns the [`std::io::ErrorKind`] of the first IO error in the chain.
+    pub fn io_kind(&self) -> Option<std::io::ErrorKind> {
+        self.downcast_ref::<std::io::Error>().map(|e| e.kind())
+    }
+
+    /// Whether retrying the failed operation may succeed.
+    ///
+    /// This is the case if the chain contains an IO error of a transient kind,
+    /// e.g. a timeout, or if the nearest [`ErrorCode`] is retryable.
+    pub fn is_retryable(&self) -> bool {
+        use std::io::ErrorKind;
+
+        if self.code().is_some_and(|code| code.is_retryable()) {
+            return true;
+        }
+        self.chain().any(|source| {
+            source.downcast_ref::<std::io::Error>().is_some_and(|e| {
+                matches!(
+                    e.kind(),
+                    ErrorKind::TimedOut
+                        | ErrorKind::ConnectionReset
+                        | ErrorKind::ConnectionAborted
+                        | ErrorKind::Interrupted
+                        | ErrorKind::WouldBlock
+                )
+            })
+        })
+    }
+
+    /// Returns the location at which context was attached to this error, if known.
+    pub fn location(&self) -> Option<&'static Location<'static>> {
+        match self {
+            Self::Source { location, .. }
+            | Self::Message { location, .. }
+            | Self::Whatever { location, .. } => *location,
+            Self::Anyhow { .. } | Self::Multiple { .. } => None,
+        }
+    }
+
+    pub(crate) fn set_location(&mut self, new: &'static Location<'static>) {
+        match self {
+            Self::Source { location, .. }
+            | Self::Message { location, .. }
+            | Self::Whatever { location, .. } => {
+                *location = Some(new);
+            }
+            Self::Anyhow { .. } | Self::Multiple { .. } => {}
+        }
+    }
+
+    /// Returns the nearest [`ErrorCode`], walking from this error down to its root cause.
+    pub fn code(&self) -> Option<ErrorCode> {
+        self.find_meta(|meta| meta.code)
+    }
+
+    /// Attaches an [`ErrorCode`] to this error, replacing any code set on this link.
+    pub fn with_code(self, code: ErrorCode) -> Self {
+        self.with_meta(|meta| meta.code = Some(code))
+    }
+
+    /// Returns the nearest process exit code, walking from this error down to its root cause.
+    ///
+    /// The outermost code wins, so callers can override codes set further down.
+    pub fn exit_code(&self) -> Option<u8> {
+        self.find_meta(|meta| meta.exit_code)
+    }
+
+    /// Attaches the code the process should exit with if this error ends it.
+    ///
+    /// See the conversion into [`ExitCode`](std::process::ExitCode).
+    pub fn with_exit_code(self, code: u8) -> Self {
+        self.with_meta(|meta| meta.exit_code = Some(code))
+    }
+
+    /// Returns the nearest sequence number, walking from this error down to its root cause.
+    pub fn seq(&self) -> Option<u64> {
+        self.find_meta(|meta| meta.seq)
+    }
+
+    /// Records the next value of a process wide counter on this error.
+    ///
+    /// Useful to order errors in logs which share the same timestamp.
+    pub fn with_seq(self) -> Self {
+        static SEQ: AtomicU64 = AtomicU64::new(0);
+        self.with_meta(|meta| meta.seq = Some(SEQ.fetch_add(1, Ordering::Relaxed)))
+    }
+
+    /// Returns the fields attached to this link, with [`Error::with_field`] or by a
+    /// registered context provider.
+    pub fn fields(&self) -> &[(String, String)] {
+        self.meta()
+            .map(|meta| meta.fields.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Returns the fields of this error and of all links below it, outermost first.
+    ///
+    /// Fields set with the same value on several links are only listed once.
+    pub fn all_fields(&self) -> Vec<(&str, &str)> {
+        let mut fields = Vec::new();
+        let mut current = Some(self);
+        while let Some(err) = current {
+            for (key, value) in err.fields() {
+                let field = (key.as_str(), value.as_str());
+                if !fields.contains(&field) {
+                    fields.push(field);
+                }
+            }
+            current = match err {
+                Self::Whatever { source, .. } => source.as_deref(),
+                _ => None,
+            };
+        }
+        fields
+    }
+
+    /// Attaches a field to this link, which is rendered below the chain by `Debug`.
+    pub fn with_field(self, key: impl Into<String>, value: impl std::fmt::Display) -> Self {
+        self.with_meta(|meta| meta.fields.push((key.into(), value.to_string())))
+    }
+
+    /// Attaches a help note to this error, e.g. a suggestion how to fix it.
+    ///
+    /// `Debug` prints the notes of all links after the chain as `help: <note>`.
+    pub fn with_help(self, help: impl Into<String>) -> Self {
+        self.with_meta(|meta| meta.help.push(help.into()))
+    }
+
+    /// Returns the outermost help note, see [`Error::with_help`].
+    pub fn help(&self) -> Option<&str> {
+        self.all_help().next()
+    }
+
+    /// Returns the help notes of this error and of all links below it, outermost first.
+    pub(crate) fn all_help(&self) -> impl Iterator<Item = &str> {
+        let mut links = Vec::new();
+        let mut current = Some(self);
+        while let Some(err) = current {
+            links.push(err);
+            current = match err {
+                Self::Whatever { source, .. } => source.as_deref(),
+                _ => None,
+            };
+        }
+        links.into_iter().flat_map(|err| {
+            err.meta()
+                .into_iter()
+                .flat_map(|meta| meta.help.iter().rev().map(String::as_str))
+        })
+    }
+
+    /// Returns when this error was created, see [`set_capture_creation`].
+    pub fn created_at(&self) -> Option<SystemTime> {
+        self.meta().and_then(|meta| meta.created_at)
+    }
+
+    /// Returns the name of the thread this error was created on, see [`set_capture_creation`].
+    ///
+    /// For unnamed threads this is the id of the thread.
+    pub fn thread_name(&self) -> Option<&str> {
+        self.meta().and_then(|meta| meta.thread_name.as_deref())
+    }
+
+    fn meta(&self) -> Option<&Meta> {
+        match self {
+            Self::Source { meta, .. }
+            | Self::Message { meta, .. }
+            | Self::Anyhow { meta, .. }
+            | Self::Whatever { meta, .. }
+            | Self::Multiple { meta, .. } => meta.as_deref(),
+        }
+    }
+
+    fn find_meta<T>(&self, f: impl Fn(&Meta) -> Option<T> + Copy) -> Option<T> {
+        let own = self.meta().and_then(f);
+        match self {
+            Self::Whatever {
+                source: Some(source),
+                ..
+            } => own.or_else(|| source.find_meta(f)),
+            _ => own,
+        }
+    }
+
+    pub(crate) fn with_meta(mut self, f: impl FnOnce(&mut Meta)) -> Self {
+        match &mut self {
+            Self::Source { meta, .. }
+            | Self::Message { meta, .. }
+            | Self::Anyhow { meta, .. }
+            | Self::Whatever { meta, .. }
+            | Self::Multiple { meta, .. } => f(meta.get_or_insert_with(Default::default)),
+        }
+        self
+    }
+
+    /// Merges two independent errors into one chain, with `self` as the parent.
+    ///
+    /// `other` becomes the source of an [`Error::Whatever`] carrying the message of
+    /// `self`, e.g. to report a failed cleanup below the error that caused it. The
+    /// span trace, backtrace and metadata of `self` are kept. If `self` already
+    /// has a chain, it is flattened into that one message, as in its `Display`.
+    pub fn and_then_cause(self, other: Error) -> Error {
+        let message = match &self {
+            Self::Whatever {
+                message: Some(message),
+                source: None,
+                ..
+            } => message.clone(),
+            this => this.to_string().into(),
+        };
+        let location = self.location();
+        let (span_trace, backtrace, meta) = match self {
+            Self::Source {
+                span_trace,
+                backtrace,
+                meta,
+                ..
+            }
+            | Self::Message {
+                span_trace,
+                backtrace,
+                meta,
+                ..
+            }
+            | Self::Anyhow {
+                span_trace,
+                backtrace,
+                meta,
+                ..
+            }
+            | Self::Whatever {
+                span_trace,
+                backtrace,
+                meta,
+                ..
+            }
+            | Self::Multiple {
+                span_trace,
+                backtrace,
+                meta,
+                ..
+            } => (span_trace, backtrace, meta),
+        };
+        Self::Whatever {
+            message: Some(message),
+            span_trace,
+            source: Some(Box::new(other)),
+            backtrace,
+            location,
+            meta,
+        }
+    }
+
+    /// Rebuilds this error with `f` applied to each message of its chain.
+    ///
+    /// Useful to redact sensitive details, e.g. paths or tokens, before handing
+    /// an error to an external client. Each message becomes an [`Error::Whatever`]
+    /// link, so the result cannot be downcast to the original error types. Span
+    /// traces, backtraces and all metadata are dropped.
+    pub fn map_messages(self, f: impl Fn(&str) -> String) -> Error {
+        let link = |message: String, source: Option<Error>| Error::Whatever {
+            message: Some(message.into()),
+            span_trace: SpanTrace::empty(),
+            source: source.map(Box::new),
+            backtrace: None,
+            location: None,
+            meta: None,
+        };
+        self.chain()
+            .rev()
+            .map(|source| f(&source.to_string()))
+            .fold(None, |source, message| Some(link(message, source)))
+            .unwrap_or_else(|| link(f(&MessageStrings::current().error), None))
+    }
+
+    /// Clones this error, if every link of it can be cloned without loss.
+    ///
+    /// This is the case for chains built only from messages, e.g. with
+    /// [`format_err!`] and [`ResultExt::context`] on `Result<T, Error>`. Errors
+    /// wrapping any other error type return `None`.
+    pub fn try_clone(&self) -> Option<Error> {
+        match self {
+            Self::Whatever {
+                message,
+                span_trace,
+                source,
+                backtrace,
+                location,
+                meta,
+            } => {
+                let source = match source {
+                    Some(source) => Some(Box::new(source.try_clone()?)),
+                    None => None,
+                };
+                Some(Self::Whatever {
+                    message: message.clone(),
+                    span_trace: span_trace.clone(),
+                    source,
+                    backtrace: backtrace.clone(),
+                    location: *location,
+                    meta: meta.clone(),
+                })
+            }
+            Self::Multiple {
+                sources,
+                span_trace,
+                backtrace,
+                meta,
+            } => Some(Self::Multiple {
+                sources: sources
+                    .iter()
+                    .map(Error::try_clone)
+                    .collect::<Option<_>>()?,
+                span_trace: span_trace.clone(),
+                backtrace: backtrace.clone(),
+                meta: meta.clone(),
+            }),
+            Self::Source { .. } | Self::Message { .. } | Self::Anyhow { .. } => None,
+        }
+    }
+
+    /// Wraps this error with an additional message, like [`ResultExt::context`].
+    #[track_caller]
+    pub fn context<C: AsRef<str>>(self, context: C) -> Error {
+        Err::<(), _>(self).context(context).unwrap_err()
+    }
+
+    /// Wraps this error with a lazily built message, like [`ResultExt::with_context`].
+    #[track_caller]
+    pub fn with_context<F: FnOnce() -> String>(self, context: F) -> Error {
+        Err::<(), _>(self).with_context(context).unwrap_err()
+    }
+
+    /// Returns how this error was constructed.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Source { .. } => ErrorKind::Wrapped,
+            Self::Message { .. } => ErrorKind::Context,
+            Self::Anyhow { .. } => ErrorKind::Anyhow,
+            Self::Whatever { .. } => ErrorKind::Adhoc,
+            Self::Multiple { .. } => ErrorKind::Multiple,
+        }
+    }
+
+    /// Returns the message attached to this error itself, without its sources.
+    ///
+    /// This is `None` for errors without an own message, e.g. those created by
+    /// [`ResultExt::e`], [`Error::anyhow`] or [`Error::multiple`].
+    pub fn message(&self) -> Option<&str> {
+        match self {
+            Self::Message { message, .. } | Self::Whatever { message, .. } => message.as_deref(),
+            Self::Source { .. } | Self::Anyhow { .. } | Self::Multiple { .. } => None,
+        }
+    }
+
+    /// Returns a builder for an [`Error::Source`], see [`ErrorBuilder`].
+    pub fn builder() -> ErrorBuilder {
+        ErrorBuilder::default()
+    }
+
+    /// Combines several independent errors into one.
+    pub fn multiple(sources: Vec<Error>) -> Self {
+        Self::Multiple {
+            sources,
+            span_trace: GenerateImplicitData::generate(),
+            backtrace: capture_backtrace(),
+            meta: Meta::capture(),
+        }
+    }
+
+    /// Wraps an [`anyhow::Error`].
+    ///
+    /// The span trace is captured here, at the conversion site. Use
+    /// [`Error::anyhow_at`] to keep a span trace captured where the error occurred.
+    pub fn anyhow(err: anyhow::Error) -> Self {
+        Self::anyhow_at(err, GenerateImplicitData::generate())
+    }
+
+    /// Wraps an [`anyhow::Error`] with an explicit span trace.
+    pub fn anyhow_at(err: anyhow::Error, span_trace: SpanTrace) -> Self {
+        Self::Anyhow {
+            source: err,
+            span_trace,
+            backtrace: capture_backtrace(),
+            meta: Meta::capture(),
+        }
+    }
+
+    /// Returns every link of this error together with the backtrace captured for it.
+    ///
+    /// The first entry is always [`Source::Root`], standing for this error itself.
+    /// It is followed by all sources, outermost first. Use this to build custom
+    /// renderers; the layout of the entries is stable.
+    pub fn stack(&self) -> Vec<(Option<Backtrace<'_>>, Source<'_>)> {
+        let mut traces = Vec::new();
+        match self {
+            Self::Source { source, .. } => {
+                // current trace
+                traces.push((self.own_backtrace(), Source::Root));
+                traces.push((source.backtrace(), Source::Formatted(source.as_ref())));
+
+                // collect the traces from our sources
+                std_stack(source.source(), &mut traces);
+            }
+            Self::Message { source, .. } => {
+                // current trace
+                traces.push((self.own_backtrace(), Source::Root));
+
+                // collect the traces from our sources
+                std_stack(Some(source.as_ref()), &mut traces);
+            }
+            Self::Anyhow { source, .. } => {
+                // current trace
+                traces.push((self.own_backtrace(), Source::Root));
+
+                anyhow_stack(source, &mut traces);
+            }
+            Self::Whatever { source, .. } => {
+                // current trace
+                traces.push((self.own_backtrace(), Source::Root));
+
+                // collect the traces from our sources
+                if let Some(s) = source.as_deref() {
+                    traces.push((s.own_backtrace(), Source::Error(s)));
+                    s.stack_inner(&mut traces);
+                }
+            }
+            Self::Multiple { sources, .. } => {
+                // current trace
+                traces.push((self.own_backtrace(), Source::Root));
+
+                for s in sources {
+                    traces.push((s.own_backtrace(), Source::Error(s)));
+                    s.stack_inner(&mut traces);
+                }
+            }
+        }
+
+        traces
+    }
+
+    /// Iterates over the messages in this error, starting with the outermost one.
+    ///
+    /// Links which carry no message of their own, e.g. those created by
+    /// [`ResultExt::e`], are skipped. Use `.rev()` to start at the root cause.
+    pub fn chain(&self) -> Chain<'_> {
+        let mut links = Vec::new();
+        self.chain_inner(&mut links);
+        Chain {
+            inner: links.into_iter(),
+        }
+    }
+
+    fn chain_inner<'a>(&'a self, links: &mut Vec<Source<'a>>) {
+        match self {
+            Self::Source { source, .. } => {
+                links.push(Source::Formatted(source.as_ref()));
+                push_links(
+                    std::iter::successors(source.source(), |s| s.source()),
+                    links,
+                );
+            }
+            Self::Message {
+                message, source, ..
+            } => {
+                if message.is_some() {
+                    links.push(Source::Error(self));
+                }
+                // look through the wrapper used for boxed errors
+                let source: &(dyn snafu::Error + 'static) =
+                    match source.downcast_ref::<BoxedError>() {
+                        Some(boxed) => boxed.0.as_ref(),
+                        None => source.as_ref(),
+                    };
+                push_links(std::iter::successors(Some(source), |s| s.source()), links);
+            }
+            Self::Anyhow { source, .. } => {
+                links.push(Source::Anyhow(source));
+                push_links(source.chain().skip(1), links);
+            }
+            Self::Whatever {
+                message, source, ..
+            } => {
+                if message.is_some() || source.is_none() {
+                    links.push(Source::Error(self));
+                }
+                if let Some(s) = source {
+                    s.chain_inner(links);
+                }
+            }
+            Self::Multiple { sources, .. } => {
+                links.push(Source::Error(self));
+                for s in sources {
+                    s.chain_inner(links);
+                }
+            }
+        }
+    }
+
+    /// The number of links in the chain of this error, including itself.
+    ///
+    /// Walks the sources without collecting them, so this is cheap even for
+    /// deep chains. The sources of an [`Error::Multiple`] are not counted.
+    /// Chains of sources which seem to loop are cut off after 1024 links.
+    pub fn depth(&self) -> usize {
+        1 + count_sources(self.skip_transparent().source(), 0, MAX_CHAIN_LEN)
+    }
+
+    /// Skips [`Error::Whatever`]s without a message, which only wrap their source.
+    fn skip_transparent(&self) -> &Error {
+        match self {
+            Self::Whatever {
+                message: None,
+                source: Some(source),
+                ..
+            } => source.skip_transparent(),
+            _ => self,
+        }
+    }
+
+    fn stack_inner<'a>(&'a self, traces: &mut Vec<(Option<Backtrace<'a>>, Source<'a>)>) {
+        match self {
+            Self::Source { source, .. } => {
+                traces.push((source.backtrace(), Source::Formatted(source.as_ref())));
+
+                // collect the traces from our sources
+                std_stack(source.source(), traces);
+            }
+            Self::Message { source, .. } => {
+                // collect the traces from our sources
+                std_stack(Some(source.as_ref()), traces);
+            }
+            Self::Anyhow { source, .. } => {
+                anyhow_stack(source, traces);
+            }
+            Self::Whatever { source, .. } => {
+                // collect the traces from our sources
+                if let Some(s) = source.as_deref() {
+                    traces.push((s.own_backtrace(), Source::Error(s)));
+                    s.stack_inner(traces);
+                }
+            }
+            Self::Multiple { sources, .. } => {
+                for s in sources {
+                    traces.push((s.own_backtrace(), Source::Error(s)));
+                    s.stack_inner(traces);
+                }
+            }
+        }
+    }
+}
+
+/// Pushes at most [`MAX_CHAIN_LEN`] sources onto `links`, followed by
+/// [`Source::Cycle`] if there are more.
+fn push_links<'a>(
+    sources: impl Iterator<Item = &'a (dyn snafu::Error + 'static)>,
+    links: &mut Vec<Source<'a>>,
+) {
+    for (i, s) in sources.enumerate() {
+        if i == MAX_CHAIN_LEN {
+            links.push(Source::Cycle);
+            break;
+        }
+        links.push(Source::SnafuError(s));
+    }
+}
+
+/// Pushes an [`anyhow::Error`] and each of its sources onto `traces`.
+///
+/// The error itself is pushed as [`Source::Anyhow`], with its backtrace. Its
+/// `chain()` always starts with that same error, followed by its sources, so
+/// skipping the first link lists every source exactly once, outermost first.
+fn anyhow_stack<'a>(
+    source: &'a anyhow::Error,
+    traces: &mut Vec<(Option<Backtrace<'a>>, Source<'a>)>,
+) {
+    let backtrace = cfg!(feature = "backtrace").then(|| Backtrace::Std(source.backtrace()));
+    traces.push((backtrace, Source::Anyhow(source)));
+
+    push_sources(source.chain().skip(1), traces);
+}
+
+/// Pushes `source` and each of its sources onto `traces`, outermost first.
+fn std_stack<'a>(
+    source: Option<&'a (dyn snafu::Error + 'static)>,
+    traces: &mut Vec<(Option<Backtrace<'a>>, Source<'a>)>,
+) {
+    push_sources(std::iter::successors(source, |s| s.source()), traces);
+}
+
+/// Pushes at most [`MAX_CHAIN_LEN`] sources, followed by [`Source::Cycle`]
+/// if there are more.
+fn push_sources<'a>(
+    sources: impl Iterator<Item = &'a (dyn snafu::Error + 'static)>,
+    traces: &mut Vec<(Option<Backtrace<'a>>, Source<'a>)>,
+) {
+    for (i, s) in sources.enumerate() {
+        if i == MAX_CHAIN_LEN {
+            traces.push((None, Source::Cycle));
+            break;
+        }
+        if let Some(this) = s.downcast_ref::<&dyn Formatted>() {
+            traces.push((this.backtrace(), Source::Formatted(*this)));
+        } else {
+            traces.push((None, Source::SnafuError(s)));
+        }
+    }
+}
+
+/// A resolved copy of the backtrace of a link, made when it is first printed.
+#[derive(Debug, Clone, Default)]
+pub struct ResolvedBacktrace(
+    // only printed with the `backtrace` feature
+    #[cfg_attr(not(feature = "backtrace"), allow(dead_code))] OnceLock<snafu::Backtrace>,
+);
+
+/// A backtrace in the [`Error::stack`] of an error.
+#[derive(Clone, Debug)]
+pub enum Backtrace<'a> {
+    /// Captured by this crate or by a snafu error.
+    Crate(&'a snafu::Backtrace),
+    /// Captured by this crate, with a place to keep it once resolved.
+    Captured(&'a snafu::Backtrace, &'a ResolvedBacktrace),
+    /// Captured by an [`anyhow::Error`].
+    Std(&'a std::backtrace::Backtrace),
+}
+
+impl<'a> Backtrace<'a> {
+    /// Returns a resolved copy of a backtrace captured without symbols.
+    ///
+    /// Symbolizing is slow, so the copy of a [`Backtrace::Captured`] is only
+    /// made once and kept with the error.
+    #[cfg(feature = "backtrace")]
+    pub(crate) fn resolve(&self) -> Option<Cow<'a, snafu::Backtrace>> {
+        fn resolved(bt: &snafu::Backtrace) -> snafu::Backtrace {
+            let mut bt = bt.clone();
+            bt.resolve();
+            bt
+        }
+        match *self {
+            Self::Crate(bt) | Self::Captured(bt, _)
+                if bt.frames().iter().all(|f| !f.symbols().is_empty()) =>
+            {
+                None
+            }
+            Self::Crate(bt) => Some(Cow::Owned(resolved(bt))),
+            Self::Captured(bt, cache) => Some(Cow::Borrowed(cache.0.get_or_init(|| resolved(bt)))),
+            Self::Std(_) => None,
+        }
+    }
+
+    /// The number of frames, before any filtering for printing.
+    #[cfg(feature = "backtrace")]
+    pub fn frames_len(&self) -> usize {
+        color_backtrace::Backtrace::frames(self).len()
+    }
+
+    /// The number of frames, before any filtering for printing.
+    ///
+    /// Without the `backtrace` feature, backtraces captured by std are not parsed.
+    #[cfg(not(feature = "backtrace"))]
+    pub fn frames_len(&self) -> usize {
+        match self {
+            Self::Crate(bt) | Self::Captured(bt, _) => bt.frames().len(),
+            Self::Std(_) => 0,
+        }
+    }
+
+    /// Whether there are no frames, e.g. as they could not be resolved.
+    pub fn is_empty(&self) -> bool {
+        self.frames_len() == 0
+    }
+}
+
+#[cfg(feature = "backtrace")]
+impl color_backtrace::Backtrace for Backtrace<'_> {
+    fn frames(&self) -> Vec<color_backtrace::Frame> {
+        match self {
+            Self::Crate(bt) | Self::Captured(bt, _) => match self.resolve() {
+                Some(resolved) => color_backtrace::Backtrace::frames(resolved.as_ref()),
+                None => color_backtrace::Backtrace::frames(*bt),
+            },
+            // nothing to parse, see `capture_backtrace`
+            Self::Std(_) if cfg!(target_family = "wasm") => Vec::new(),
+            Self::Std(bt) => {
+                // no comment, things are sad in std land
+                match btparse::deserialize(bt) {
+                    Ok(parsed_bt) => color_backtrace::Backtrace::frames(&parsed_bt),
+                    // never panic while formatting an error
+                    Err(_) => Vec::new(),
+                }
+            }
+        }
+    }
+}
+
+/// Renders the frames with the configured [`FormatOptions`] and frame filters.
+#[cfg(feature = "backtrace")]
+impl std::fmt::Display for Backtrace<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let opts = FormatOptions::current();
+        f.write_str(&opts.format_trace(&opts.printer(), self))
+    }
+}
+
+/// A link in the [`Error::stack`] or [`Error::chain`] of an error.
+pub enum Source<'a> {
+    /// The error the stack was taken of itself.
+    Root,
+    /// An error wrapped by [`Error::Source`], e.g. converted with `From`.
+    Formatted(&'a (dyn Formatted + 'static)),
+    /// Any other error in the chain.
+    SnafuError(&'a (dyn snafu::Error + 'static)),
+    /// An [`Error`] wrapped by another one.
+    Error(&'a Error),
+    /// An [`anyhow::Error`] wrapped by [`Error::Anyhow`].
+    Anyhow(&'a anyhow::Error),
+    /// Stands for the remaining sources of a chain that was cut off, as it
+    /// seemed to loop back on itself.
+    Cycle,
+}
+
+impl core::fmt::Display for Source<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Self::Root => write!(f, "{}", MessageStrings::current().root),
+            Self::Cycle => write!(f, "{}", MessageStrings::current().cycle),
+            Self::Formatted(e) => e.fmt(f),
+            Self::Error(e) => match e {
+                Error::Message {
+                    message: Some(message),
+                    ..
+                }
+                | Error::Whatever {
+                    message: Some(message),
+                    ..
+                } => write!(f, "{message}"),
+                Error::Multiple { sources, .. } => {
+                    write!(
+                        f,
+                        "{} {}",
+                        sources.len(),
+                        MessageStrings::current().multiple
+                    )
+                }
+                _ => e.fmt(f),
+            },
+            Self::SnafuError(e) => e.fmt(f),
+            Self::Anyhow(e) => e.fmt(f),
+        }
+    }
+}
+
+impl<'a> Source<'a> {
+    /// The message of this link, as rendered by `Display`.
+    pub fn message(&self) -> String {
+        self.to_string()
+    }
+
+    /// Whether this is the [`Source::Root`] entry of a stack.
+    pub fn is_root(&self) -> bool {
+        matches!(self, Self::Root)
+    }
+
+    fn downcast_ref<T: snafu::Error + 'static>(&self) -> Option<&'a T> {
+        match *self {
+            Source::Formatted(e) => e.as_error_source().downcast_ref(),
+            Source::SnafuError(e) => e.downcast_ref(),
+            Source::Anyhow(e) => AsRef::<dyn snafu::Error + Send + Sync>::as_ref(e).downcast_ref(),
+            Source::Root | Source::Error(_) | Source::Cycle => None,
+        }
+    }
+}
+
+/// Iterator over the links of an [`Error`], see [`Error::chain`].
+pub struct Chain<'a> {
+    inner: std::vec::IntoIter<Source<'a>>,
+}
+
+impl<'a> Iterator for Chain<'a> {
+    type Item = Source<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl DoubleEndedIterator for Chain<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back()
+    }
+}
+
+impl ExactSizeIterator for Chain<'_> {}
+
+impl snafu::ErrorCompat for Error {
+    fn backtrace(&self) -> Option<&snafu::Backtrace> {
+        self.find_backtrace().map(|(_, bt)| bt)
+    }
+}
+
+trait ErrorSource<'a>: std::fmt::Display + std::fmt::Debug {
+    fn source(&'a self) -> Option<SourceWrapper<'a>>;
+}
+
+impl<'a> ErrorSource<'a> for Error {
+    fn source(&'a self) -> Option<SourceWrapper<'a>> {
+        match self {
+            Error::Source { source, .. } => source.source().map(SourceWrapper::Std),
+            Error::Anyhow { source, .. } => source.source().map(SourceWrapper::Std),
+            Error::Message { ref source, .. } => Some(SourceWrapper::Box(source)),
+            Error::Whatever { ref source, .. } => source
+                .as_deref()
+                .map(|s| SourceWrapper::Crate(s.skip_transparent())),
+            Error::Multiple { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum SourceWrapper<'a> {
+    Std(&'a dyn std::error::Error),
+    #[allow(clippy::borrowed_box)]
+    Box(&'a Box<dyn snafu::Error + Sync + Send + 'static>),
+    Crate(&'a Error),
+}
+
+impl<'a> std::fmt::Display for SourceWrapper<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SourceWrapper::Std(error) => write!(f, "{error}"),
+            SourceWrapper::Crate(error) => match error {
+                Error::Message { message, .. } => {
+                    if let Some(message) = message {
+                        write!(f, "{message}")
+                    } else {
+                        write!(f, "{}", MessageStrings::current().error)
+                    }
+                }
+                Error::Whatever {
+                    message: Some(message),
+                    ..
+                } => write!(f, "{message}"),
+                _ => write!(f, "{}", NoPrefix(error, TreeStyle::default())),
+            },
+            SourceWrapper::Box(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl<'a> ErrorSource<'a> for SourceWrapper<'a> {
+    fn source(&'a self) -> Option<SourceWrapper<'a>> {
+        match self {
+            SourceWrapper::Std(error) => std::error::Error::source(error).map(SourceWrapper::Std),
+            SourceWrapper::Crate(error) => error.source(),
+            SourceWrapper::Box(error) => error.source().map(SourceWrapper::Std),
+        }
+    }
+}
+
+fn write_sources_if_alternate(
+    f: &mut core::fmt::Formatter,
+    source: Option<SourceWrapper<'_>>,
+    style: TreeStyle,
+) -> core::fmt::Result {
+    if !f.alternate() {
+        return Ok(());
+    }
+    write_sources(f, source, style)?;
+    Ok(())
+}
+
+fn write_sources(
+    f: &mut core::fmt::Formatter,
+    source: Option<SourceWrapper<'_>>,
+    style: TreeStyle,
+) -> core::fmt::Result {
+    write_sources_inner(f, source, 0, style)?;
+    Ok(())
+}
+
+fn write_sources_inner(
+    f: &mut core::fmt::Formatter,
+    source: Option<SourceWrapper<'_>>,
+    i: usize,
+    style: TreeStyle,
+) -> core::fmt::Result {
+    if let Some(current) = source {
+        if i == MAX_CHAIN_LEN {
+            write!(f, "\n  {i}: {}", MessageStrings::current().cycle)?;
+            return Ok(());
+        }
+        if style.max_sources == Some(i) {
+            let more = count_sources(Some(current), i, MAX_CHAIN_LEN);
+            write!(f, "\n  ... ({more} more)")?;
+            return Ok(());
+        }
+        if let SourceWrapper::Crate(error @ Error::Multiple { .. }) = current {
+            // each of the errors has its own chain, which is indented below
+            let tree = format!("{:#}", NoPrefix(error, style));
+            write!(f, "\n  {i}: {}", tree.replace('\n', "\n     "))?;
+            return Ok(());
+        }
+        match style.max_message_len {
+            Some(max) => write!(
+                f,
+                "\n  {i}: {}",
+                truncate_message(&current.to_string(), max)
+            )?,
+            None => write!(f, "\n  {i}: {current}")?,
+        }
+        write_sources_inner(f, current.source(), i + 1, style)?;
+    }
+    Ok(())
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        self.fmt_span_field(f)?;
+        self.fmt_message(f, TreeStyle::default())
+    }
+}
+
+/// Displays an [`Error`] without the span field prefix, for errors in a chain.
+struct NoPrefix<'a>(&'a Error, TreeStyle);
+
+impl core::fmt::Display for NoPrefix<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        self.0.fmt_message(f, self.1)
+    }
+}
+
+/// How the errors of nested [`Error::Multiple`]s are marked in the alternate `Display`.
+#[derive(Debug, Clone, Copy, Default)]
+struct TreeStyle {
+    /// Whether the `[i]` markers are colored by their depth.
+    colors: bool,
+    /// The number of [`Error::Multiple`]s around the current one.
+    depth: usize,
+    /// Caps the length of each message, see [`FormatOptions::max_message_len`].
+    max_message_len: Option<usize>,
+    /// Caps the length of each chain, see [`FormatOptions::max_sources`].
+    max_sources: Option<usize>,
+}
+
+impl TreeStyle {
+    fn nested(self) -> Self {
+        Self {
+            depth: self.depth + 1,
+            ..self
+        }
+    }
+
+    fn marker(self, i: usize) -> String {
+        const COLORS: [u8; 4] = [36, 33, 35, 32];
+        if self.colors {
+            let color = COLORS[self.depth % COLORS.len()];
+            format!("\x1b[{color}m[{i}]\x1b[0m")
+        } else {
+            format!("[{i}]")
+        }
+    }
+}
+
+impl Error {
+    /// Returns the span field captured for this link, see [`crate::set_display_span_field`].
+    pub fn span_field(&self) -> Option<(&str, &str)> {
+        self.meta()
+            .and_then(|meta| meta.span_field.as_ref())
+            .map(|(key, value)| (*key, value.as_str()))
+    }
+
+    fn fmt_span_field(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        if let Some((key, value)) = self.skip_transparent().span_field() {
+            write!(f, "[{key}={value}] ")?;
+        }
+        Ok(())
+    }
+
+    fn fmt_message(&self, f: &mut core::fmt::Formatter, style: TreeStyle) -> core::fmt::Result {
+        if let Self::Whatever {
+            message: None,
+            source: Some(source),
+            ..
+        } = self
+        {
+            // without a message this only wraps its source, as in `chain()`
+            return source.fmt_message(f, style);
+        }
+        match style.max_message_len {
+            // the errors of an `Error::Multiple` are truncated on their own
+            Some(max) if !matches!(self, Self::Multiple { .. }) => {
+                let head = if f.alternate() {
+                    format!("{:#}", Head(self, style))
+                } else {
+                    Head(self, style).to_string()
+                };
+                f.write_str(&truncate_message(&head, max))?;
+            }
+            _ => self.fmt_head(f, style)?,
+        }
+        write_sources_if_alternate(f, self.source(), style)
+    }
+
+    /// Writes the message of this error, without the list of its sources.
+    fn fmt_head(&self, f: &mut core::fmt::Formatter, style: TreeStyle) -> core::fmt::Result {
+        match self {
+            Self::Source { source, .. } => {
+                write!(f, "{source}")?;
+            }
+            Self::Whatever {
+                message, source, ..
+            } => match (source, message) {
+                (Some(source), Some(message)) => {
+                    if f.alternate() {
+                        write!(f, "{message}")?;
+                    } else {
+                        write!(f, "{message}: {}", NoPrefix(source, style))?;
+                    }
+                }
+                (None, Some(message)) => {
+                    write!(f, "{message}")?;
+                }
+                (Some(source), None) => {
+                    source.fmt_head(f, style)?;
+                }
+                (None, None) => {
+                    write!(f, "{}", MessageStrings::current().error)?;
+                }
+            },
+            Self::Message {
+                message, source, ..
+            } => {
+                if let Some(message) = message {
+                    // `NoneError` adds nothing beyond the message itself
+                    if source.is::<NoneError>() {
+                        write!(f, "{message}")?;
+                    } else {
+                        write!(f, "{message}: {source}")?;
+                    }
+                } else {
+                    write!(f, "{source}")?;
+                }
+            }
+            Self::Anyhow { source, .. } => core::fmt::Display::fmt(source, f)?,
+            Self::Multiple { sources, .. } => {
+                write!(
+                    f,
+                    "{} {}",
+                    sources.len(),
+                    MessageStrings::current().multiple
+                )?;
+                for (i, source) in sources.iter().enumerate() {
+                    if f.alternate() {
+                        // indent the chain of each error below its header
+                        let chain = format!("{:#}", NoPrefix(source, style.nested()))
+                            .replace('\n', "\n    ");
+                        write!(f, "\n{} {chain}", style.marker(i))?;
+                    } else {
+                        let sep = if i == 0 { ":" } else { "," };
+                        write!(f, "{sep} [{i}] {}", NoPrefix(source, style))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Displays the message of an [`Error`] without its sources, to truncate it.
+struct Head<'a>(&'a Error, TreeStyle);
+
+impl core::fmt::Display for Head<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        self.0.fmt_head(f, self.1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use snafu::Snafu;
+
+    use super::*;
+
+    // `Error` is routinely moved across threads and tasks.
+    const _: fn() = || {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Error>();
+    };
+
+    #[test]
+    fn test_anyhow_compat() -> Result {
+        fn ok() -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        ok().map_err(Error::anyhow)?;
+
+        Ok(())
+    }
+
+    #[derive(Debug, Snafu)]
+    enum MyError {
+        #[snafu(display("A failure"))]
+        A,
+    }
+
+    #[test]
+    fn test_whatever() {
+        // `whatever!` records where it is called
+        const FAIL_LINE: u32 = line!() + 2;
+        fn fail() -> Result {
+            snafu::whatever!("sad face");
+        }
+
+        fn fail_my_error() -> Result<(), MyError> {
+            Err(ASnafu.build())
+        }
+
+        const WHATEVER_LINE: u32 = line!() + 2;
+        fn fail_whatever() -> Result {
+            snafu::whatever!(fail(), "sad");
+            Ok(())
+        }
+
+        fn fail_whatever_my_error() -> Result {
+            snafu::whatever!(fail_my_error(), "sad");
+            Ok(())
+        }
+
+        assert!(fail().is_err());
+        assert_eq!(
+            format!("{:?}", fail().unwrap_err()),
+            format!("sad face\nat {}:{FAIL_LINE}", file!())
+        );
+        assert_eq!(format!("{}", fail().unwrap_err()), "sad face");
+        assert!(fail_my_error().is_err());
+        assert!(fail_whatever().is_err());
+        assert!(fail_whatever_my_error().is_err());
+
+        assert_eq!(
+            format!("{:?}", fail_whatever().unwrap_err()),
+            format!("sad\n  0: sad face\nat {}:{WHATEVER_LINE}", file!())
+        );
+
+        assert_eq!(
+            format!("{:?}", fail_whatever()),
+            format!("Err(sad\n  0: sad face\nat {}:{WHATEVER_LINE})", file!())
+        );
+    }
+
+    #[test]
+    fn test_context_none() {
+        fn fail() -> Result {
+            None.context("sad")
+        }
+
+        assert!(fail().is_err());
+    }
+
+    #[test]
+    fn test_format_err() {
+        fn fail() -> Result {
+            Err(format_err!("sad: {}", 12))
+        }
+
+        assert!(fail().is_err());
+    }
+
+    #[test]
+    fn test_io_err() {
+        fn fail_io() -> std::io::Result<()> {
+            Err(std::io::Error::other("sad IO"))
+        }
+
+        fn fail_custom() -> Result<(), MyError> {
+            Ok(())
+        }
+
+        fn fail_outer() -> Result {
+            fail_io().e()?;
+            fail_custom()?;
+            Ok(())
+        }
+        let err = fail_outer().unwrap_err();
+        assert_eq!(err.to_string(), "sad IO");
+    }
+
+    #[test]
+    fn test_message() {
+        fn fail_box() -> Result<(), impl snafu::Error + Send + Sync + 'static> {
+            Err(Box::new(std::io::Error::other("foo")))
+        }
+
+        let my_res = fail_box().context("failed");
+
+        let err = my_res.unwrap_err();
+        let stack = err.stack();
+        assert_eq!(stack.len(), 2);
+    }
+
+    #[test]
+    fn test_option() {
+        fn fail_opt() -> Option<()> {
+            None
+        }
+
+        let my_res = fail_opt().context("failed");
+
+        let err = my_res.unwrap_err();
+        let stack = err.stack();
+        assert_eq!(stack.len(), 2);
+    }
+
+    #[test]
+    fn test_sources() {
+        let err = std::io::Error::new(std::io::ErrorKind::NotFound, "file not found");
+        let file_name = "foo.txt";
+        let res: Result<(), _> = Err(err).with_context(|| format!("failed to read {file_name}"));
+        let res: Result<(), _> = res.context("read error");
+
+        let err = res.err().unwrap();
+
+        let fmt = format!("{err}");
+        println!("short:\n{fmt}\n");
+        assert_eq!(&fmt, "read error: failed to read foo.txt: file not found");
+
+        let fmt = format!("{err:#}");
+        println!("alternate:\n{fmt}\n");
+        assert_eq!(
+            &fmt,
+            r#"read error
+  0: failed to read foo.txt
+  1: file not found"#
+        );
+
+        let fmt = format!("{err:?}");
+        println!("debug:\n{fmt}\n");
+    }
+
+    #[test]
+    fn test_ensure() {
+        fn check(value: usize) -> Result {
+            ensure!(value < 10, "value too large: {}", value);
+            Ok(())
+        }
+
+        let captures = crate::spantrace::captures();
+        assert!(check(1).is_ok());
+        assert_eq!(crate::spantrace::captures(), captures);
+
+        let err = check(12).unwrap_err();
+        assert_eq!(err.to_string(), "value too large: 12");
+        assert_eq!(crate::spantrace::captures(), captures + 1);
+    }
+
+    #[test]
+    fn test_chain_rev() {
+        let err = std::io::Error::new(std::io::ErrorKind::NotFound, "file not found");
+        let res: Result<(), _> = Err(err).context("failed to read foo.txt");
+        let err = res.context("read error").unwrap_err();
+
+        let chain: Vec<_> = err.chain().map(|s| s.to_string()).collect();
+        assert_eq!(
+            chain,
+            ["read error", "failed to read foo.txt", "file not found"]
+        );
+
+        let rev: Vec<_> = err.chain().rev().map(|s| s.to_string()).collect();
+        assert_eq!(
+            rev,
+            ["file not found", "failed to read foo.txt", "read error"]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "backtrace")]
+    fn test_unparseable_std_backtrace() {
+        let bt = std::backtrace::Backtrace::disabled();
+        let frames = color_backtrace::Backtrace::frames(&Backtrace::Std(&bt));
+        assert!(frames.is_empty());
+
+        let opts = FormatOptions::default();
+        opts.format_trace(&opts.printer(), &Backtrace::Std(&bt));
+    }
+
+    #[derive(Clone, Default)]
+    struct CapturedEvents(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+
+    impl CapturedEvents {
+        fn take(&self) -> Vec<String> {
+            std::mem::take(&mut *self.0.lock().unwrap())
+        }
+    }
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for CapturedEvents {
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            struct Visitor<'a>(&'a mut String);
+
+            impl tracing::field::Visit for Visitor<'_> {
+                fn record_debug(
+                    &mut self,
+                    field: &tracing::field::Field,
+                    value: &dyn std::fmt::Debug,
+                ) {
+                    use std::fmt::Write;
+
+                    if !self.0.is_empty() {
+                        self.0.push(' ');
+                    }
+                    write!(self.0, "{}={:?}", field.name(), value).unwrap();
+                }
+            }
+
+            let mut line = format!("{} ", event.metadata().level());
+            event.record(&mut Visitor(&mut line));
+            self.0.lock().unwrap().push(line);
+        }
+    }
+
+    /// Runs `f` with a subscriber that records all emitted events.
+    fn capture_events<R>(f: impl FnOnce() -> R) -> (R, Vec<String>) {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let events = CapturedEvents::default();
+        let subscriber = tracing_subscriber::registry().with(events.clone());
+        let res = tracing::subscriber::with_default(subscriber, f);
+        (res, events.take())
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn test_deep_chain_warning() {
+        let max = MAX_CONTEXT_DEPTH.load(Ordering::Relaxed);
+
+        let (_, events) = capture_events(|| {
+            let mut res: Result = Err(format_err!("sad"));
+            for i in 0..max + 5 {
+                res = res.context(format!("attempt {i}"));
+            }
+        });
+
+        assert_eq!(events.len(), 1);
+        assert!(events[0].starts_with("WARN "));
+        assert!(events[0].contains("error chain exceeds"));
+
+        // an error which is its own source, so its chain is longer than `max`
+        #[derive(Debug)]
+        struct Loop;
+
+        impl std::fmt::Display for Loop {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "loop")
+            }
+        }
+
+        impl std::error::Error for Loop {
+            fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+                Some(self)
+            }
+        }
+
+        let (_, events) = capture_events(|| {
+            let mut res = Err::<(), _>(Loop).e();
+            for i in 0..5 {
+                res = res.context(format!("attempt {i}"));
+            }
+        });
+        assert_eq!(events.len(), 1);
+        assert!(events[0].contains("error chain exceeds"));
+    }
+
+    #[test]
+    fn test_context_std() {
+        fn fail() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+            Err("boxed failure".into())
+        }
+
+        let err = fail().context_std("outer").unwrap_err();
+        assert_eq!(err.to_string(), "outer: boxed failure");
+        assert_eq!(err.chain().count(), 2);
+    }
+
+    #[test]
+    fn test_try_ctx_location() {
+        fn fail_io() -> std::io::Result<()> {
+            Err(std::io::Error::other("sad IO"))
+        }
+
+        fn inner() -> Result {
+            try_ctx!(fail_io(), "inner");
+            Ok(())
+        }
+        let line = line!() - 3;
+
+        let err = inner().context("outer").unwrap_err();
+        let outer_line = line!() - 1;
+
+        assert_eq!(err.location().unwrap().line(), outer_line);
+        let Error::Whatever {
+            source: Some(inner),
+            ..
+        } = &err
+        else {
+            panic!("expected a wrapped error");
+        };
+        let location = inner.location().unwrap();
+        assert_eq!(location.file(), file!());
+        assert_eq!(location.line(), line);
+    }
+
+    #[test]
+    fn test_option_display() {
+        let err = None::<()>.context("missing value").unwrap_err();
+        assert_eq!(err.to_string(), "missing value");
+
+        let err = None::<()>.e().unwrap_err();
+        assert_eq!(err.to_string(), "Expected some, found none");
+    }
+
+    #[derive(Debug, Snafu)]
+    #[snafu(display("inner failure"))]
+    struct InnerError {
+        backtrace: snafu::Backtrace,
+    }
+
+    #[test]
+    fn test_debug_labels_backtraces() {
+        fn fail_inner() -> Result<(), InnerError> {
+            InnerSnafu.fail()
+        }
+
+        fn fail() -> Result {
+            fail_inner()?;
+            Ok(())
+        }
+
+        let err = fail().context("outer").unwrap_err();
+        let fmt = format!("{err:?}");
+        assert!(fmt.contains(": inner failure\n"));
+        assert!(!fmt.contains("<empty backtrace>"));
+    }
+
+    #[test]
+    fn test_dedup_backtrace() {
+        let inner = Error::Whatever {
+            message: Some("inner".into()),
+            span_trace: GenerateImplicitData::generate(),
+            source: None,
+            backtrace: Some(snafu::Backtrace::new()),
+            location: None,
+            meta: None,
+        };
+
+        let err = Error::Whatever {
+            message: Some("outer".into()),
+            span_trace: GenerateImplicitData::generate(),
+            backtrace: wrapping_backtrace_inner(&inner, true),
+            source: Some(Box::new(inner)),
+            location: None,
+            meta: None,
+        };
+
+        let backtraces = err.stack().iter().filter(|(bt, _)| bt.is_some()).count();
+        assert_eq!(backtraces, 1);
+    }
+
+    #[test]
+    fn test_map_context() {
+        let ok: Result<usize, std::io::Error> = Ok(2);
+        assert_eq!(ok.map_context(|v| v * 2, "doubling").unwrap(), 4);
+
+        let err: Result<usize, std::io::Error> = Err(std::io::Error::other("sad IO"));
+        let err = err.map_context(|v| v * 2, "doubling").unwrap_err();
+        assert_eq!(err.to_string(), "doubling: sad IO");
+    }
+
+    #[test]
+    fn test_debug_location() {
+        let res: Result<(), std::io::Error> = Err(std::io::Error::other("sad IO"));
+        let err = res.context("reading").unwrap_err();
+        let line = line!() - 1;
+
+        let location = err.location().unwrap();
+        assert_eq!(location.file(), file!());
+        assert_eq!(location.line(), line);
+
+        let fmt = format!("{err:?}");
+        assert!(fmt.contains(&format!("\nat {}:{line}", file!())));
+    }
+
+    #[test]
+    fn test_io_kind() {
+        let err = std::io::Error::new(std::io::ErrorKind::NotFound, "file not found");
+        let res: Result<(), _> = Err(err).context("reading");
+        let err = res.context("loading config").unwrap_err();
+        assert_eq!(err.io_kind(), Some(std::io::ErrorKind::NotFound));
+
+        let boxed: Box<dyn std::error::Error + Send + Sync> =
+            Box::new(std::io::Error::from(std::io::ErrorKind::TimedOut));
+        let err = Err::<(), _>(boxed).context_std("boxed").unwrap_err();
+        assert_eq!(err.io_kind(), Some(std::io::ErrorKind::TimedOut));
+
+        assert_eq!(format_err!("no io").io_kind(), None);
+    }
+
+    #[test]
+    fn test_downcast_ref() {
+        fn fail() -> Result {
+            Err(ASnafu.build())?;
+            Ok(())
+        }
+
+        let err = fail().context("outer").unwrap_err();
+        assert!(matches!(err.downcast_ref::<MyError>(), Some(MyError::A)));
+        assert!(err.downcast_ref::<std::io::Error>().is_none());
+    }
+
+    #[test]
+    fn test_error_code() {
+        const NOT_FOUND: ErrorCode = ErrorCode::new("not_found");
+        const UNAVAILABLE: ErrorCode = ErrorCode::new("unavailable");
+
+        fn fail() -> Result {
+            let err = std::io::Error::from(std::io::ErrorKind::NotFound);
+            Err(err).context_code(NOT_FOUND, "missing")
+        }
+
+        let err = fail().unwrap_err();
+        assert_eq!(err.code(), Some(NOT_FOUND));
+        assert_eq!(err.to_string(), "missing: entity not found");
+
+        let err = fail().context("outer").context("outermost").unwrap_err();
+        assert_eq!(err.code(), Some(NOT_FOUND));
+
+        // the nearest code wins
+        let err = fail().context_code(UNAVAILABLE, "outer").unwrap_err();
+        assert_eq!(err.code(), Some(UNAVAILABLE));
+
+        let err = Error::from(ASnafu.build()).with_code(UNAVAILABLE);
+        assert_eq!(err.code(), Some(UNAVAILABLE));
+        assert_eq!(err.to_string(), "A failure");
+
+        assert_eq!(format_err!("no code").code(), None);
+    }
+
+    #[test]
+    fn test_seq() {
+        let first = format_err!("first").with_seq();
+        let second = Err::<(), _>(ASnafu.build())
+            .context("second")
+            .map_err(Error::with_seq)
+            .context("outer")
+            .unwrap_err();
+
+        let (first, second) = (first.seq().unwrap(), second.seq().unwrap());
+        assert!(first < second);
+        assert_eq!(format_err!("none").seq(), None);
+
+        let err = Error::from(ASnafu.build()).with_seq();
+        assert!(format!("{err:?}").contains(&format!("seq {}", err.seq().unwrap())));
+    }
+
+    #[test]
+    fn test_try_clone() {
+        let res: Result = Err(format_err!("inner"));
+        let err = res.context("outer").unwrap_err().with_seq();
+        let clone = err.try_clone().unwrap();
+        assert_eq!(format!("{clone:#}"), format!("{err:#}"));
+        assert_eq!(clone.seq(), err.seq());
+        assert_eq!(clone.location(), err.location());
+
+        let err = Error::anyhow(anyhow::anyhow!("anyhow"));
+        assert!(err.try_clone().is_none());
+
+        let res: Result = Err(Error::from(ASnafu.build()));
+        assert!(res.context("outer").unwrap_err().try_clone().is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "backtrace")]
+    fn test_backtrace_display() {
+        let err = Error::Whatever {
+            message: Some("sad".into()),
+            span_trace: GenerateImplicitData::generate(),
+            source: None,
+            backtrace: Some(snafu::Backtrace::new()),
+            location: None,
+            meta: None,
+        };
+
+        let s = err.backtrace().unwrap().to_string();
+        assert!(s.contains("test_backtrace_display"), "{s}");
+    }
+
+    #[test]
+    fn test_error_context() {
+        let line = line!() + 1;
+        let err = format_err!("inner").context("outer");
+        assert_eq!(format!("{err:#}"), "outer\n  0: inner");
+        assert_eq!(err.location().unwrap().line(), line);
+
+        let err = err.with_context(|| "outermost".to_string());
+        assert_eq!(err.to_string(), "outermost: outer: inner");
+    }
+
+    #[test]
+    fn test_whatever_context() {
+        struct Foreign;
+
+        impl std::fmt::Display for Foreign {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "foreign failure")
+            }
+        }
+
+        let err = Err::<(), _>(Foreign)
+            .whatever_context("calling foreign crate")
+            .unwrap_err();
+        assert_eq!(err.to_string(), "calling foreign crate: foreign failure");
+        assert_eq!(err.chain().len(), 1);
+        assert!(err.location().is_some());
+    }
+
+    #[test]
+    fn test_try_collect_errors() {
+        fn parse(s: &str) -> Result<u32> {
+            s.parse::<u32>()
+                .with_context(|| format!("invalid number {s:?}"))
+        }
+
+        let values = try_collect_errors(["1", "2"].into_iter().map(parse)).unwrap();
+        assert_eq!(values, [1, 2]);
+
+        let err = try_collect_errors(["1", "x", "y"].into_iter().map(parse)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "2 errors occurred: [0] invalid number \"x\": invalid digit found in string, \
+             [1] invalid number \"y\": invalid digit found in string"
+        );
+        assert_eq!(
+            format!("{err:#}"),
+            "2 errors occurred\n\
+             [0] invalid number \"x\": invalid digit found in string\n      \
+             0: invalid digit found in string\n\
+             [1] invalid number \"y\": invalid digit found in string\n      \
+             0: invalid digit found in string"
+        );
+
+        // the root, then each child followed by its sources
+        let stack = err.stack();
+        assert_eq!(stack.len(), 5);
+        assert_eq!(stack[1].1.to_string(), "invalid number \"x\"");
+        assert_eq!(stack[3].1.to_string(), "invalid number \"y\"");
+        assert_eq!(err.chain().len(), 5);
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        let io = std::io::Error::from(std::io::ErrorKind::TimedOut);
+        let err = Err::<(), _>(io).context("connect").unwrap_err();
+        assert!(err.context("outer").is_retryable());
+
+        let io = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        assert!(!Err::<(), _>(io).e().unwrap_err().is_retryable());
+
+        let err = None::<()>.context("missing").unwrap_err();
+        assert!(!err.is_retryable());
+
+        let err = format_err!("busy").with_code(ErrorCode::retryable("busy"));
+        assert!(err.is_retryable());
+    }
+
+    #[test]
+    fn test_display_chain() {
+        let err = Error::Whatever {
+            message: Some("middle".into()),
+            span_trace: GenerateImplicitData::generate(),
+            source: Some(Box::new(format_err!("inner"))),
+            backtrace: Some(snafu::Backtrace::new()),
+            location: None,
+            meta: None,
+        }
+        .context("outer");
+
+        let chain = err.display_chain();
+        assert_eq!(chain, "1: outer\n2: middle\n3: inner");
+        assert!(!chain.contains("\x1b[") && !chain.contains("test_display_chain"));
+
+        let err = format_err!("same").context("same");
+        assert_eq!(err.display_chain(), "1: same");
+    }
+
+    #[test]
+    fn test_context_light() {
+        let before = crate::spantrace::captures();
+        let err = "x"
+            .parse::<u32>()
+            .context_light("parse")
+            .context_light("outer")
+            .unwrap_err();
+        assert_eq!(crate::spantrace::captures(), before);
+
+        assert!(err.backtrace().is_none());
+        assert!(!err.span_trace().is_captured());
+        assert_eq!(
+            err.to_string(),
+            "outer: parse: invalid digit found in string"
+        );
+        assert!(err.location().is_some());
+
+        let err = None::<()>.with_context_light(|| "none".into()).unwrap_err();
+        assert!(err.backtrace().is_none());
+    }
+
+    #[cfg(target_family = "wasm")]
+    #[test]
+    fn test_wasm_debug() {
+        let err = Error::anyhow(anyhow::anyhow!("inner")).context("outer");
+        let fmt = format!("{err:?}");
+        assert!(fmt.starts_with("outer"));
+    }
+
+    #[test]
+    fn test_emit() {
+        let err = format_err!("inner").context("outer");
+        let line = line!() + 1;
+        let (_, events) = capture_events(|| err.emit());
+
+        assert_eq!(events.len(), 1);
+        assert!(events[0].starts_with("ERROR "));
+        assert!(events[0].contains("error=outer: inner"), "{}", events[0]);
+        assert!(events[0].contains(r#"chain="1: outer\n2: inner""#));
+        assert!(events[0].contains(&format!("caller={}:{line}:", file!())));
+    }
+
+    #[test]
+    fn test_context_formatted() {
+        fn fail() -> Result<(), InnerError> {
+            InnerSnafu.fail()
+        }
+
+        let line = line!() + 1;
+        let err = fail().context_formatted("outer").unwrap_err();
+        assert_eq!(err.to_string(), "outer: inner failure");
+        assert_eq!(err.location().unwrap().line(), line);
+
+        let inner = fail().unwrap_err();
+        let stack = err.stack();
+        let (bt, source) = stack
+            .iter()
+            .find(|(_, source)| matches!(source, Source::Formatted(_)))
+            .unwrap();
+        assert_eq!(source.to_string(), "inner failure");
+        assert_eq!(bt.is_some(), cfg!(feature = "backtrace"));
+
+        // through `ResultExt::context` the backtrace of the source is lost
+        let err = Err::<(), _>(inner).context("outer").unwrap_err();
+        assert!(err.stack().iter().skip(1).all(|(bt, _)| bt.is_none()));
+    }
+
+    #[test]
+    fn test_context_fmt() {
+        let path = "foo.txt";
+        let line = line!() + 1;
+        let err = context_fmt!("x".parse::<u32>(), "reading {path}").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "reading foo.txt: invalid digit found in string"
+        );
+        assert_eq!(err.location().unwrap().line(), line);
+
+        let err = context_fmt!(None::<()>, "no {}", 1).unwrap_err();
+        assert_eq!(err.to_string(), "no 1");
+
+        let mut called = false;
+        let mut side_effect = || {
+            called = true;
+            "value"
+        };
+        let value = context_fmt!("1".parse::<u32>(), "{}", side_effect()).unwrap();
+        assert_eq!(value, 1);
+        assert!(!called);
+    }
+
+    #[test]
+    fn test_assert_error_chain() {
+        let err = "x"
+            .parse::<u32>()
+            .context("parse config")
+            .context("load")
+            .unwrap_err();
+
+        assert!(err.matches_chain(&["load", "parse", "invalid digit"]));
+        assert!(!err.matches_chain(&["load", "parse"]));
+        assert!(!err.matches_chain(&["parse", "load", "invalid digit"]));
+        assert_error_chain!(err, ["load", "parse config", "invalid digit"]);
+
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            assert_error_chain!(err, ["load", "other", ""])
+        }));
+        let message = *res.unwrap_err().downcast::<String>().unwrap();
+        assert_eq!(
+            message,
+            "error chain does not match\n\
+             expected:\n1: load\n2: other\n3: \n\
+             actual:\n1: load\n2: parse config\n3: invalid digit found in string"
+        );
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_anyhow_at() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let subscriber = tracing_subscriber::registry().with(crate::ErrorLayer::default());
+        tracing::subscriber::with_default(subscriber, || {
+            let span_trace = tracing::info_span!("origin")
+                .in_scope(|| SpanTrace::from(tracing_error::SpanTrace::capture()));
+
+            let err = Error::anyhow_at(anyhow::anyhow!("failed"), span_trace.clone());
+            assert_eq!(err.span_trace().to_string(), span_trace.to_string());
+            assert!(err.span_trace().to_string().contains("origin"));
+
+            let err = Error::anyhow(anyhow::anyhow!("failed"));
+            assert!(!err.span_trace().is_captured());
+        });
+    }
+
+    #[test]
+    fn test_stack_inspection() {
+        let err = Error::Whatever {
+            message: Some("outer".into()),
+            span_trace: GenerateImplicitData::generate(),
+            source: Some(Box::new(format_err!("inner"))),
+            backtrace: Some(snafu::Backtrace::new()),
+            location: None,
+            meta: None,
+        };
+
+        let stack = err.stack();
+        assert!(stack[0].1.is_root());
+        let root_bt = stack[0].0.as_ref().unwrap();
+        assert!(!root_bt.is_empty());
+        assert!(root_bt.frames_len() > 0);
+
+        assert!(!stack[1].1.is_root());
+        assert_eq!(stack[1].1.message(), "inner");
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    #[test]
+    fn test_without_tracing() {
+        let err = format_err!("boom").context("outer");
+        assert!(!err.span_trace().is_captured());
+        assert!(err.span_trace().spans().is_empty());
+
+        let debug = format!("{err:?}");
+        assert!(debug.starts_with("outer"), "{debug}");
+        assert!(
+            !debug.contains(&*MessageStrings::current().span_trace),
+            "{debug}"
+        );
+    }
+
+    #[test]
+    fn test_builder() {
+        let before = crate::spantrace::captures();
+        let err = Error::builder()
+            .source(MyError::A)
+            .backtrace(Some(snafu::Backtrace::from(Vec::new())))
+            .span_trace(SpanTrace::empty())
+            .build();
+        assert_eq!(crate::spantrace::captures(), before);
+        assert!(matches!(err, Error::Source { .. }));
+        assert_eq!(err.to_string(), "A failure");
+        assert!(err.own_backtrace().is_some_and(|bt| bt.is_empty()));
+        // empty backtraces are skipped
+        assert!(err.backtrace().is_none());
+        assert!(!err.span_trace().is_captured());
+
+        let err = Error::builder().source(MyError::A).backtrace(None).build();
+        assert!(err.backtrace().is_none());
+    }
+
+    #[test]
+    fn test_into_boxed_std_error() {
+        let err = format_err!("inner").context("middle").context("outer");
+        let boxed: Box<dyn std::error::Error + Send + Sync> = err.into();
+        assert_eq!(boxed.to_string(), "outer");
+        assert_eq!(format!("{boxed:?}"), "outer\n  0: middle\n  1: inner");
+
+        let mut messages = Vec::new();
+        let mut source = boxed.source();
+        while let Some(s) = source {
+            messages.push(s.to_string());
+            source = s.source();
+        }
+        assert_eq!(messages, ["middle", "inner"]);
+
+        fn fail() -> std::result::Result<(), Box<dyn std::error::Error>> {
+            Err(format_err!("failed"))?;
+            Ok(())
+        }
+        assert_eq!(fail().unwrap_err().to_string(), "failed");
+    }
+
+    #[test]
+    fn test_to_plain_string() {
+        let build = || Error::Whatever {
+            message: Some("outer".into()),
+            span_trace: SpanTrace::empty(),
+            source: Some(Box::new(
+                Error::builder()
+                    .source(MyError::A)
+                    .backtrace(None)
+                    .span_trace(SpanTrace::empty())
+                    .build(),
+            )),
+            backtrace: None,
+            location: None,
+            meta: None,
+        };
+        let plain = build().to_plain_string();
+        assert_eq!(plain, "outer\n  0: A failure");
+        assert_eq!(plain, build().to_plain_string());
+
+        let err = format_err!("failed");
+        assert!(!err.to_plain_string().contains('\x1b'));
+    }
+
+    #[test]
+    fn test_backtraces() {
+        let err = Error::Whatever {
+            message: Some("outer".into()),
+            span_trace: SpanTrace::empty(),
+            source: Some(Box::new(
+                Error::builder()
+                    .source(MyError::A)
+                    .backtrace(Some(snafu::Backtrace::new()))
+                    .build(),
+            )),
+            backtrace: Some(snafu::Backtrace::new()),
+            location: None,
+            meta: None,
+        };
+        assert_eq!(err.backtraces().len(), 2);
+
+        let err = Error::builder()
+            .source(MyError::A)
+            .backtrace(Some(snafu::Backtrace::from(Vec::new())))
+            .build();
+        assert!(err.backtraces().is_empty());
+    }
+
+    #[test]
+    fn test_whatever_display() {
+        let whatever = |message: Option<&'static str>, source: bool| Error::Whatever {
+            message: message.map(Into::into),
+            span_trace: SpanTrace::empty(),
+            source: source.then(|| Box::new(format_err!("inner").context("middle"))),
+            backtrace: None,
+            location: None,
+            meta: None,
+        };
+
+        let err = whatever(Some("outer"), true);
+        assert_eq!(err.to_string(), "outer: middle: inner");
+        assert_eq!(format!("{err:#}"), "outer\n  0: middle\n  1: inner");
+
+        // without a message only the source is shown, without any gap
+        let err = whatever(None, true);
+        assert_eq!(err.to_string(), "middle: inner");
+        assert_eq!(format!("{err:#}"), "middle\n  0: inner");
+        assert_eq!(err.display_chain(), "1: middle\n2: inner");
+
+        let err = whatever(Some("outer"), false);
+        assert_eq!(err.to_string(), "outer");
+        assert_eq!(format!("{err:#}"), "outer");
+
+        let err = whatever(None, false);
+        assert_eq!(err.to_string(), "unknown error");
+        assert_eq!(format!("{err:#}"), "unknown error");
+
+        // a transparent link in the middle of the chain is skipped as well
+        let err = whatever(None, true).context("outer");
+        assert_eq!(format!("{err:#}"), "outer\n  0: middle\n  1: inner");
+
+        let labels: Vec<_> = whatever(Some("outer"), true)
+            .stack()
+            .iter()
+            .map(|(_, source)| source.to_string())
+            .collect();
+        assert_eq!(labels, ["Root", "middle", "inner"]);
+    }
+
+    #[test]
+    fn test_depth() {
+        let err = format_err!("inner");
+        assert_eq!(err.depth(), 1);
+
+        let err = err.context("middle").context("outer");
+        assert_eq!(err.depth(), 3);
+        assert_eq!(err.depth(), err.chain().len());
+
+        let err = Err::<(), _>(MyError::A).context("outer").unwrap_err();
+        assert_eq!(err.depth(), 2);
+
+        // wrapping without a message adds no link
+        let err = Error::Whatever {
+            message: None,
+            span_trace: SpanTrace::empty(),
+            source: Some(Box::new(err)),
+            backtrace: None,
+            location: None,
+            meta: None,
+        };
+        assert_eq!(err.depth(), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "backtrace")]
+    fn test_lazy_backtrace() {
+        let unresolved = snafu::Backtrace::new_unresolved();
+        let mut resolved = unresolved.clone();
+        resolved.resolve();
+
+        let opts = FormatOptions {
+            colors: Some(false),
+            verbosity: Some(Verbosity::Full),
+            ..Default::default()
+        };
+        let printer = opts.printer();
+        let lazy = opts.format_trace(&printer, &Backtrace::Crate(&unresolved));
+        let eager = opts.format_trace(&printer, &Backtrace::Crate(&resolved));
+        assert_eq!(lazy, eager);
+        assert!(lazy.contains("test_lazy_backtrace"), "{lazy}");
+    }
+
+    #[test]
+    #[cfg(feature = "backtrace")]
+    fn test_backtrace_resolved_once() {
+        let err = Error::builder()
+            .source(MyError::A)
+            .backtrace(Some(snafu::Backtrace::new_unresolved()))
+            .build()
+            .with_meta(|_| {});
+        let resolve = || match err.own_backtrace().unwrap().resolve() {
+            Some(Cow::Borrowed(resolved)) => resolved as *const snafu::Backtrace,
+            _ => panic!("expected the kept copy"),
+        };
+        let first = resolve();
+        assert_eq!(resolve(), first);
+        err.to_plain_string();
+        assert_eq!(resolve(), first);
+    }
+
+    #[test]
+    fn test_flatten_context() {
+        let res: Result<Result<u32, MyError>, Error> = Ok(Ok(1));
+        assert_eq!(res.flatten_context("build").unwrap(), 1);
+
+        let res: Result<Result<u32, MyError>, Error> = Ok(Err(MyError::A));
+        let err = res.flatten_context("build").unwrap_err();
+        assert_eq!(err.display_chain(), "1: build\n2: A failure");
+        assert!(err.downcast_ref::<MyError>().is_some());
+        assert!(err.location().is_some());
+
+        let res: Result<Result<u32, MyError>, Error> = Err(format_err!("outer"));
+        let err = res.flatten_context("build").unwrap_err();
+        assert_eq!(err.display_chain(), "1: build\n2: outer");
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_verbosity_sections() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let subscriber = tracing_subscriber::registry().with(crate::ErrorLayer::default());
+        tracing::subscriber::with_default(subscriber, || {
+            let _guard = tracing::info_span!("verbosity").entered();
+            let err = Error::builder()
+                .source(MyError::A)
+                .backtrace(Some(snafu::Backtrace::new()))
+                .build();
+            let render = |verbosity| {
+                let opts = FormatOptions {
+                    verbosity: Some(verbosity),
+                    colors: Some(false),
+                    ..Default::default()
+                };
+                format!("{:?}", WithOptions(&err, &opts))
+            };
+
+            assert_eq!(render(Verbosity::Minimal), "A failure");
+
+            let strings = MessageStrings::current();
+            for verbosity in [Verbosity::Medium, Verbosity::Full] {
+                let out = render(verbosity);
+                assert!(out.contains(&*strings.span_trace), "{out}");
+                let backtrace = out.contains(&*strings.backtrace_for);
+                assert_eq!(backtrace, cfg!(feature = "backtrace"), "{out}");
+            }
+        });
+    }
+
+    #[test]
+    fn test_fields() {
+        let err = format_err!("read failed")
+            .with_field("path", "/etc/x")
+            .context("load")
+            .with_field("attempt", 3);
+        assert_eq!(err.fields(), [("attempt".to_string(), "3".to_string())]);
+        assert_eq!(err.all_fields(), [("attempt", "3"), ("path", "/etc/x")]);
+
+        let fmt = format!("{err:?}");
+        assert!(
+            fmt.starts_with("load\n  0: read failed\n  attempt = 3\n  path = /etc/x"),
+            "{fmt}"
+        );
+    }
+
+    #[test]
+    fn test_context_indexed() {
+        let res = ["1", "2", "x", "4"]
+            .iter()
+            .enumerate()
+            .map(|(i, s)| s.parse::<u32>().context_indexed(i))
+            .collect::<Result<Vec<_>>>();
+        let err = res.unwrap_err();
+        assert_eq!(err.to_string(), "item 2: invalid digit found in string");
+        assert!(matches!(err, Error::Message { .. }));
+    }
+
+    #[test]
+    fn test_context_owned() {
+        let owned = "x"
+            .parse::<u32>()
+            .context_owned("parse".to_string())
+            .unwrap_err();
+        let borrowed = "x".parse::<u32>().context("parse").unwrap_err();
+        assert_eq!(owned.display_chain(), borrowed.display_chain());
+        assert!(matches!(owned, Error::Message { .. }));
+        assert_eq!(owned.location().unwrap().file(), file!());
+    }
+
+    #[test]
+    fn test_e_on_error() {
+        let err = format_err!("inner").context("outer");
+        let depth = err.depth();
+        let stack = err.stack().len();
+
+        let err = Err::<(), _>(err).e().unwrap_err();
+        assert_eq!(err.depth(), depth);
+        assert_eq!(err.stack().len(), stack);
+        assert_eq!(err.display_chain(), "1: outer\n2: inner");
+    }
+
+    #[test]
+    fn test_help() {
+        let err = format_err!("inner")
+            .with_help("check the input")
+            .context("outer")
+            .with_help("try running with --force");
+        assert_eq!(err.help(), Some("try running with --force"));
+        assert_eq!(format_err!("no help").help(), None);
+
+        let out = err.to_plain_string();
+        assert!(
+            out.starts_with(
+                "outer\n  0: inner\nhelp: try running with --force\nhelp: check the input"
+            ),
+            "{out}"
+        );
+
+        let opts = FormatOptions {
+            colors: Some(true),
+            ..FormatOptions::plain()
+        };
+        let out = format!("{:?}", WithOptions(&err, &opts));
+        assert!(
+            out.contains("\x1b[36mhelp: try running with --force\x1b[0m"),
+            "{out}"
+        );
     }
-}
 
-impl<'a> ErrorSource<'a> for SourceWrapper<'a> {
-    fn source(&'a self) -> Option<SourceWrapper<'a>> {
-        match self {
-            SourceWrapper::Std(error) => std::error::Error::source(error).map(SourceWrapper::Std),
-            SourceWrapper::Crate(error) => error.source(),
-            SourceWrapper::Box(error) => error.source().map(SourceWrapper::Std),
+    #[test]
+    fn test_from_iter() {
+        let err: Error = std::iter::empty().collect();
+        assert_eq!(err.to_string(), "no errors");
+
+        let single = format_err!("inner").context("outer");
+        let depth = single.depth();
+        let err: Error = vec![single].into_iter().collect();
+        assert!(matches!(err, Error::Whatever { .. }));
+        assert_eq!(err.depth(), depth);
+        assert_eq!(err.to_string(), "outer: inner");
+
+        let err: Error = vec![format_err!("a"), format_err!("b")]
+            .into_iter()
+            .collect();
+        match err {
+            Error::Multiple { sources, .. } => assert_eq!(sources.len(), 2),
+            _ => panic!("expected Error::Multiple"),
         }
     }
-}
 
-fn write_sources_if_alternate(
-    f: &mut core::fmt::Formatter,
-    source: Option<SourceWrapper<'_>>,
-) -> core::fmt::Result {
-    if !f.alternate() {
-        return Ok(());
+    #[test]
+    fn test_context_if() {
+        let res: Result<(), MyError> = Err(MyError::A);
+        let err = res
+            .context_if(|err| matches!(err, MyError::A), "check your input")
+            .unwrap_err();
+        assert_eq!(err.depth(), 2);
+        assert_eq!(err.to_string(), "check your input: A failure");
+
+        let res: Result<(), MyError> = Err(MyError::A);
+        let err = res.context_if(|_| false, "check your input").unwrap_err();
+        assert_eq!(err.depth(), 1);
+        assert_eq!(err.to_string(), "A failure");
     }
-    write_sources(f, source)?;
-    Ok(())
-}
 
-fn write_sources(
-    f: &mut core::fmt::Formatter,
-    source: Option<SourceWrapper<'_>>,
-) -> core::fmt::Result {
-    write_sources_inner(f, source, 0)?;
-    Ok(())
-}
+    #[test]
+    fn test_anyhow_stack_order() {
+        let source = anyhow::Error::new(std::io::Error::other("inner"))
+            .context("middle")
+            .context("outer");
+        let err = Error::anyhow(source);
 
-fn write_sources_inner(
-    f: &mut core::fmt::Formatter,
-    source: Option<SourceWrapper<'_>>,
-    i: usize,
-) -> core::fmt::Result {
-    if let Some(current) = source {
-        write!(f, "\n  {i}: {current}")?;
-        write_sources_inner(f, current.source(), i + 1)?;
+        let labels: Vec<_> = err.stack().iter().map(|(_, s)| s.to_string()).collect();
+        assert_eq!(labels, ["Root", "outer", "middle", "inner"]);
     }
-    Ok(())
-}
 
-impl core::fmt::Display for Error {
-    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
-        match self {
-            Self::Source { source, .. } => {
-                write!(f, "{source}")?;
-            }
-            Self::Whatever {
-                message, source, ..
-            } => match (source, message) {
-                (Some(source), Some(message)) => {
-                    if f.alternate() {
-                        write!(f, "{message}")?;
-                    } else {
-                        write!(f, "{message}: {source}")?;
-                    }
-                }
-                (None, Some(message)) => {
-                    write!(f, "{message}")?;
-                }
-                (Some(source), None) => {
-                    write!(f, "{source}")?;
-                }
-                (None, None) => {
-                    write!(f, "Error")?;
-                }
-            },
-            Self::Message {
-                message, source, ..
-            } => {
-                if let Some(message) = message {
-                    write!(f, "{message}: {source}")?;
-                } else {
-                    write!(f, "{source}")?;
-                }
-            }
-            Self::Anyhow { source, .. } => source.fmt(f)?,
-        }
-        write_sources_if_alternate(f, self.source())
+    #[test]
+    fn test_map_messages() {
+        let res: Result = Err(format_err!("auth failed for token=s3cret"));
+        let err = res
+            .context("request to peer with token=s3cret failed")
+            .context("sync failed")
+            .unwrap_err();
+
+        let err = err.map_messages(|message| message.replace("s3cret", "***"));
+        let chain = err.display_chain();
+        assert!(!chain.contains("s3cret"), "{chain}");
+        assert_eq!(
+            chain,
+            "1: sync failed\n2: request to peer with token=*** failed\n3: auth failed for token=***"
+        );
+        assert!(err.backtrace().is_none());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use snafu::Snafu;
+    #[test]
+    fn test_unwrap_or_log() {
+        let (value, events) = capture_events(|| "12".parse::<u32>().unwrap_or_log(7));
+        assert_eq!(value, 12);
+        assert!(events.is_empty());
 
-    use super::*;
+        let (value, events) = capture_events(|| "x".parse::<u32>().unwrap_or_log(7));
+        assert_eq!(value, 7);
+        assert_eq!(events.len(), 1);
+        assert!(events[0].starts_with("ERROR "), "{}", events[0]);
+        assert!(events[0].contains("invalid digit"), "{}", events[0]);
+
+        let (value, events) = capture_events(|| None::<u32>.unwrap_or_else_log(|| 3));
+        assert_eq!(value, 3);
+        assert_eq!(events.len(), 1);
+    }
 
     #[test]
-    fn test_anyhow_compat() -> Result {
-        fn ok() -> anyhow::Result<()> {
-            Ok(())
-        }
+    fn test_kind() {
+        let err: Error = MyError::A.into();
+        assert_eq!(err.kind(), ErrorKind::Wrapped);
 
-        ok().map_err(Error::anyhow)?;
+        let err = "x".parse::<u32>().context("parse").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Context);
 
-        Ok(())
+        let err = Error::anyhow(anyhow::anyhow!("sad"));
+        assert_eq!(err.kind(), ErrorKind::Anyhow);
+
+        let err = format_err!("sad");
+        assert_eq!(err.kind(), ErrorKind::Adhoc);
+        let err = Err::<(), _>(err).context("outer").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Adhoc);
+
+        let err = Error::multiple(vec![format_err!("a"), format_err!("b")]);
+        assert_eq!(err.kind(), ErrorKind::Multiple);
     }
 
-    #[derive(Debug, Snafu)]
-    enum MyError {
-        #[snafu(display("A failure"))]
-        A,
+    #[test]
+    fn test_debug_nested_multiple() {
+        let two_level = |outer: &str, inner: &str| {
+            Err::<(), _>(format_err!("{inner}"))
+                .context(outer)
+                .unwrap_err()
+        };
+        let nested = Error::multiple(vec![two_level("c", "c0"), format_err!("d")]);
+        let err = Error::multiple(vec![two_level("a", "a0"), nested]);
+        let err = Err::<(), _>(err).context("sync failed").unwrap_err();
+
+        let out = err.to_plain_string();
+        assert!(
+            out.starts_with(
+                "sync failed\n  \
+                 0: 2 errors occurred\n     \
+                 [0] a\n           \
+                 0: a0\n     \
+                 [1] 2 errors occurred\n         \
+                 [0] c\n               \
+                 0: c0\n         \
+                 [1] d\n"
+            ),
+            "{out}"
+        );
+
+        // the markers are colored by the depth of their aggregate
+        let opts = FormatOptions {
+            colors: Some(true),
+            ..FormatOptions::plain()
+        };
+        let out = format!("{:?}", WithOptions(&err, &opts));
+        assert!(
+            out.contains("\x1b[36m[1]\x1b[0m 2 errors occurred"),
+            "{out}"
+        );
+        assert!(out.contains("\x1b[33m[0]\x1b[0m c"), "{out}");
     }
 
     #[test]
-    fn test_whatever() {
-        fn fail() -> Result {
-            snafu::whatever!("sad face");
-        }
+    fn test_assert_err_matches() {
+        let res: Result<u32> = "x".parse::<u32>().context("parse config");
+        assert_err_matches!(res, "config: invalid digit");
 
-        fn fail_my_error() -> Result<(), MyError> {
-            Err(ASnafu.build())
-        }
+        let panic_message = |f: &dyn Fn()| {
+            let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+            *res.unwrap_err().downcast::<String>().unwrap()
+        };
 
-        fn fail_whatever() -> Result {
-            snafu::whatever!(fail(), "sad");
-            Ok(())
-        }
+        let message = panic_message(&|| assert_err_matches!(res, "connection refused"));
+        assert!(
+            message.starts_with(
+                "error does not contain \"connection refused\"\nactual:\n\
+                 parse config: invalid digit found in string\n  \
+                 0: invalid digit found in string\n"
+            ),
+            "{message}"
+        );
+        assert!(message.contains("\nat src/error.rs:"), "{message}");
 
-        fn fail_whatever_my_error() -> Result {
-            snafu::whatever!(fail_my_error(), "sad");
-            Ok(())
-        }
+        let ok: Result<u32> = Ok(1);
+        let message = panic_message(&|| assert_err_matches!(ok, "sad"));
+        assert_eq!(message, "expected error containing \"sad\", got Ok(..)");
+    }
 
-        assert!(fail().is_err());
-        assert_eq!(format!("{:?}", fail().unwrap_err()), "sad face");
-        assert_eq!(format!("{}", fail().unwrap_err()), "sad face");
-        assert!(fail_my_error().is_err());
-        assert!(fail_whatever().is_err());
-        assert!(fail_whatever_my_error().is_err());
+    #[test]
+    fn test_deterministic() {
+        let build = || {
+            let err = Error::builder()
+                .source(MyError::A)
+                .backtrace(Some(snafu::Backtrace::new()))
+                .span_trace(SpanTrace::empty())
+                .build();
+            Err::<(), _>(err).context("outer").unwrap_err()
+        };
+        let a = build();
+        // captured on another thread, with different frames
+        let b = std::thread::spawn(build).join().unwrap();
+
+        let opts = FormatOptions {
+            colors: Some(false),
+            deterministic: Some(true),
+            ..Default::default()
+        };
+        let a = format!("{:?}", WithOptions(&a, &opts));
+        let b = format!("{:?}", WithOptions(&b, &opts));
+        assert_eq!(a, b);
+        assert!(
+            a.lines()
+                .take(3)
+                .eq(["outer", "  0: A failure", "at src/error.rs"]),
+            "{a}"
+        );
+        let omitted = a.ends_with("\n<backtrace omitted>\n");
+        assert_eq!(omitted, cfg!(feature = "backtrace"), "{a}");
+    }
+
+    #[test]
+    fn test_context_static() {
+        let err = "x".parse::<u32>().context_static("parse").unwrap_err();
+        assert_eq!(err.to_string(), "parse: invalid digit found in string");
+        assert!(matches!(
+            err,
+            Error::Message {
+                message: Some(Cow::Borrowed("parse")),
+                ..
+            }
+        ));
 
+        let err = Err::<(), _>(err).context_static("outer").unwrap_err();
         assert_eq!(
-            format!("{:?}", fail_whatever().unwrap_err()),
-            "sad\n  0: sad face"
+            err.display_chain(),
+            "1: outer\n2: parse\n3: invalid digit found in string"
         );
 
-        assert_eq!(format!("{:?}", fail_whatever()), "Err(sad\n  0: sad face)");
+        let err = None::<()>.context_static("missing").unwrap_err();
+        assert_eq!(err.to_string(), "missing");
     }
 
+    #[cfg(feature = "tracing")]
     #[test]
-    fn test_context_none() {
-        fn fail() -> Result {
-            None.context("sad")
-        }
+    fn test_span_trace_unsupported_hint() {
+        use tracing_error::SpanTraceStatus;
+        use tracing_subscriber::layer::SubscriberExt;
 
-        assert!(fail().is_err());
+        let render = |verbosity| {
+            let err = Error::builder()
+                .source(MyError::A)
+                .backtrace(None)
+                .span_trace(tracing_error::SpanTrace::capture().into())
+                .build();
+            let opts = FormatOptions {
+                verbosity: Some(verbosity),
+                colors: Some(false),
+                ..Default::default()
+            };
+            (
+                err.span_trace().status(),
+                format!("{:?}", WithOptions(&err, &opts)),
+            )
+        };
+        let hint = "span trace unavailable";
+
+        let subscriber = tracing_subscriber::registry();
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("no_error_layer");
+            let _guard = span.enter();
+            let (status, out) = render(Verbosity::Full);
+            assert_eq!(status, SpanTraceStatus::UNSUPPORTED);
+            assert!(out.contains(hint), "{out}");
+            let (_, out) = render(Verbosity::Medium);
+            assert!(!out.contains(hint), "{out}");
+        });
+
+        let subscriber = tracing_subscriber::registry().with(crate::ErrorLayer::default());
+        tracing::subscriber::with_default(subscriber, || {
+            let (status, out) = render(Verbosity::Full);
+            assert_eq!(status, SpanTraceStatus::EMPTY);
+            assert!(!out.contains(hint), "{out}");
+
+            let span = tracing::info_span!("with_error_layer");
+            let _guard = span.enter();
+            let (status, out) = render(Verbosity::Full);
+            assert_eq!(status, SpanTraceStatus::CAPTURED);
+            assert!(!out.contains(hint), "{out}");
+            assert!(out.contains("with_error_layer"), "{out}");
+        });
     }
 
     #[test]
-    fn test_format_err() {
-        fn fail() -> Result {
-            Err(format_err!("sad: {}", 12))
-        }
+    fn test_warn_on_err() {
+        let (value, events) = capture_events(|| "12".parse::<u32>().warn_on_err());
+        assert_eq!(value, Some(12));
+        assert!(events.is_empty());
 
-        assert!(fail().is_err());
+        let (value, events) = capture_events(|| "x".parse::<u32>().warn_on_err());
+        assert_eq!(value, None);
+        assert_eq!(events.len(), 1);
+        assert!(events[0].starts_with("WARN "), "{}", events[0]);
+        assert!(events[0].contains("invalid digit"), "{}", events[0]);
+
+        let (value, events) = capture_events(|| "x".parse::<u32>().warn_on_err_context("parse"));
+        assert_eq!(value, None);
+        assert_eq!(events.len(), 1);
+        assert!(
+            events[0].contains(r#"chain="1: parse\n2: invalid digit found in string""#),
+            "{}",
+            events[0]
+        );
     }
 
     #[test]
-    fn test_io_err() {
-        fn fail_io() -> std::io::Result<()> {
-            Err(std::io::Error::other("sad IO"))
-        }
+    fn test_and_then_cause() {
+        let err = format_err!("write failed").and_then_cause(format_err!("cleanup failed"));
+        assert_eq!(err.display_chain(), "1: write failed\n2: cleanup failed");
+        assert_eq!(err.depth(), 2);
 
-        fn fail_custom() -> Result<(), MyError> {
-            Ok(())
+        let primary = "x".parse::<u32>().context("parse").unwrap_err();
+        let err = primary.and_then_cause(format_err!("rollback failed"));
+        assert_eq!(
+            err.display_chain(),
+            "1: parse: invalid digit found in string\n2: rollback failed"
+        );
+        assert!(err.location().is_some());
+    }
+
+    #[test]
+    fn test_from_location() {
+        fn fail() -> Result<(), MyError> {
+            Err(MyError::A)
         }
 
-        fn fail_outer() -> Result {
-            fail_io().e()?;
-            fail_custom()?;
+        fn convert() -> Result {
+            fail()?;
             Ok(())
         }
-        let err = fail_outer().unwrap_err();
-        assert_eq!(err.to_string(), "sad IO");
+        let line = line!() - 3;
+
+        let err = convert().unwrap_err();
+        assert!(matches!(err, Error::Source { .. }));
+        let location = err.location().unwrap();
+        assert_eq!((location.file(), location.line()), (file!(), line));
     }
 
     #[test]
-    fn test_message() {
-        fn fail_box() -> Result<(), impl snafu::Error + Send + Sync + 'static> {
-            Err(Box::new(std::io::Error::other("foo")))
-        }
+    #[cfg(feature = "backtrace")]
+    fn test_compact_frames() {
+        use std::hint::black_box;
 
-        let my_res = fail_box().context("failed");
+        fn nested(depth: usize) -> Error {
+            match depth {
+                0 => Error::builder()
+                    .source(MyError::A)
+                    .backtrace(Some(snafu::Backtrace::new()))
+                    .span_trace(SpanTrace::empty())
+                    .build(),
+                depth => nested(black_box(depth - 1)),
+            }
+        }
 
-        let err = my_res.unwrap_err();
-        let stack = err.stack();
-        assert_eq!(stack.len(), 2);
+        let err = nested(5);
+        let opts = FormatOptions {
+            colors: Some(false),
+            compact_frames: Some(3),
+            ..Default::default()
+        };
+        let out = format!("{:?}", WithOptions(&err, &opts));
+        let (_, trace) = out.split_once("Backtrace for 0: A failure\n").unwrap();
+        let lines: Vec<_> = trace.lines().filter(|line| !line.is_empty()).collect();
+        assert_eq!(lines.len(), 4, "{out}");
+        assert!(
+            lines[..3].iter().all(|line| line.starts_with("    ")),
+            "{out}"
+        );
+        assert!(
+            !lines[..3].iter().any(|line| line.contains("\x1b")),
+            "{out}"
+        );
+        assert!(
+            lines[3].starts_with("    (+") && lines[3].ends_with(" frames)"),
+            "{out}"
+        );
     }
 
     #[test]
-    fn test_option() {
-        fn fail_opt() -> Option<()> {
+    fn test_own_message() {
+        let err = Err::<(), _>(ASnafu.build()).context("reading config");
+        assert_eq!(err.unwrap_err().message(), Some("reading config"));
+        let err = format_err!("inner").context("outer");
+        assert_eq!(err.to_string(), "outer: inner");
+        assert_eq!(err.message(), Some("outer"));
+        assert_eq!(format_err!("root").message(), Some("root"));
+
+        assert_eq!(
+            Err::<(), _>(ASnafu.build()).e().unwrap_err().message(),
             None
+        );
+        assert_eq!(Error::from(ASnafu.build()).message(), None);
+        assert_eq!(Error::anyhow(anyhow::anyhow!("any")).message(), None);
+        assert_eq!(Error::multiple(vec![format_err!("a")]).message(), None);
+    }
+
+    #[test]
+    fn test_source_cycle() {
+        // an error which is its own source
+        #[derive(Debug)]
+        struct Cycle;
+
+        impl std::fmt::Display for Cycle {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "cycle")
+            }
         }
 
-        let my_res = fail_opt().context("failed");
+        impl std::error::Error for Cycle {
+            fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+                Some(self)
+            }
+        }
+
+        let err = Err::<(), _>(Cycle).context("outer").unwrap_err();
+        assert_eq!(err.depth(), 1 + MAX_CHAIN_LEN);
+
+        let chain: Vec<_> = err.chain().map(|s| s.to_string()).collect();
+        assert_eq!(chain.len(), 2 + MAX_CHAIN_LEN);
+        assert_eq!(chain.last().unwrap(), "(cycle detected)");
+        assert!(err
+            .display_chain()
+            .ends_with("2: cycle\n3: (cycle detected)"));
 
-        let err = my_res.unwrap_err();
         let stack = err.stack();
-        assert_eq!(stack.len(), 2);
+        assert!(matches!(stack.last(), Some((None, Source::Cycle))));
+
+        let alternate = format!("{err:#}");
+        assert!(alternate.ends_with(&format!("\n  {MAX_CHAIN_LEN}: (cycle detected)")));
+        let debug = format!("{:?}", WithOptions(&err, &FormatOptions::plain()));
+        assert!(debug.contains("(cycle detected)"));
+
+        let err = Error::anyhow(anyhow::Error::new(Cycle));
+        assert!(matches!(err.stack().last(), Some((None, Source::Cycle))));
+        assert_eq!(err.chain().last().unwrap().to_string(), "(cycle detected)");
     }
 
     #[test]
-    fn test_sources() {
-        let err = std::io::Error::new(std::io::ErrorKind::NotFound, "file not found");
-        let file_name = "foo.txt";
-        let res: Result<(), _> = Err(err).with_context(|| format!("failed to read {file_name}"));
-        let res: Result<(), _> = res.context("read error");
+    fn test_context_with_source() {
+        let earlier = std::io::Error::new(std::io::ErrorKind::NotFound, "journal missing");
+        let err = "x"
+            .parse::<u32>()
+            .context_with_source("failed to roll back", earlier)
+            .unwrap_err();
+        assert_eq!(
+            err.display_chain(),
+            "1: failed to roll back\n2: invalid digit found in string\n3: journal missing"
+        );
+        assert_eq!(err.io_kind(), Some(std::io::ErrorKind::NotFound));
 
-        let err = res.err().unwrap();
+        let res: Result<u32, Error> = Ok(1);
+        let earlier = std::io::Error::other("unused");
+        assert_eq!(res.context_with_source("unused", earlier).unwrap(), 1);
+    }
 
-        let fmt = format!("{err}");
-        println!("short:\n{fmt}\n");
-        assert_eq!(&fmt, "read error: failed to read foo.txt: file not found");
+    #[test]
+    #[cfg(not(feature = "backtrace"))]
+    fn test_without_backtrace_feature() {
+        let err = Err::<(), _>(MyError::A).context("outer").unwrap_err();
+        let err = Error::anyhow(anyhow::anyhow!("any")).and_then_cause(err);
+        assert!(err.backtrace().is_none());
+        assert!(err.backtraces().is_empty());
+        assert!(err.stack().iter().all(|(bt, _)| bt.is_none()));
 
-        let fmt = format!("{err:#}");
-        println!("alternate:\n{fmt}\n");
+        let opts = FormatOptions {
+            colors: Some(false),
+            verbosity: Some(Verbosity::Full),
+            ..Default::default()
+        };
+        let debug = format!("{:?}", WithOptions(&err, &opts));
+        assert!(
+            debug.starts_with("any\n  0: outer\n  1: A failure"),
+            "{debug}"
+        );
+        assert!(!debug.contains(&*MessageStrings::current().backtrace_for));
+    }
+
+    #[test]
+    fn test_exit_code() {
+        use std::process::ExitCode;
+
+        let err = format_err!("not found").with_exit_code(2);
+        assert_eq!(err.exit_code(), Some(2));
+        let err = err.context("load config").context("startup");
+        assert_eq!(err.exit_code(), Some(2));
+        assert_eq!(ExitCode::from(&err), ExitCode::from(2));
+
+        // the outermost code takes precedence
+        let err = err.with_exit_code(3).context("main");
+        assert_eq!(err.exit_code(), Some(3));
+
+        let err = format_err!("failed");
+        assert_eq!(err.exit_code(), None);
+        assert_eq!(ExitCode::from(&err), ExitCode::FAILURE);
+    }
+
+    #[test]
+    fn test_max_message_len_multiple() {
+        let err = Error::multiple(vec![
+            format_err!("first failure"),
+            format_err!("inner failure").context("second failure"),
+        ])
+        .context("overall failure");
+        let opts = FormatOptions {
+            max_message_len: Some(5),
+            // only the chain, as it is compared exactly
+            verbosity: Some(Verbosity::Minimal),
+            ..FormatOptions::plain()
+        };
+        let out = format!("{:?}", WithOptions(&err, &opts));
         assert_eq!(
-            &fmt,
-            r#"read error
-  0: failed to read foo.txt
-  1: file not found"#
+            out,
+            "overa…\n  \
+             0: 2 errors occurred\n     \
+             [0] first…\n     \
+             [1] secon…\n           \
+             0: inner…"
         );
+    }
 
-        let fmt = format!("{err:?}");
-        println!("debug:\n{fmt}\n");
+    #[test]
+    fn test_max_sources_multiple() {
+        let multiple = || {
+            Error::multiple(vec![
+                format_err!("inner failure")
+                    .context("middle failure")
+                    .context("first failure"),
+                format_err!("second failure"),
+            ])
+        };
+        let opts = FormatOptions {
+            max_sources: Some(1),
+            // only the chain, as it is compared exactly
+            verbosity: Some(Verbosity::Minimal),
+            ..FormatOptions::plain()
+        };
+
+        let err = multiple()
+            .context("outer failure")
+            .context("overall failure");
+        let out = format!("{:?}", WithOptions(&err, &opts));
+        assert_eq!(
+            out,
+            "overall failure\n  \
+             0: outer failure\n  \
+             ... (1 more)"
+        );
+
+        // each of the errors of an `Error::Multiple` is capped on its own
+        let out = format!("{:?}", WithOptions(&multiple(), &opts));
+        assert_eq!(
+            out,
+            "2 errors occurred\n\
+             [0] first failure\n      \
+             0: middle failure\n      \
+             ... (1 more)\n\
+             [1] second failure"
+        );
     }
 }
@@ -0,0 +1,513 @@
+use std::{
+    borrow::Cow,
+    sync::{Arc, OnceLock, RwLock},
+};
+#[cfg(feature = "backtrace")]
+use std::{
+    cell::{Cell, RefCell},
+    path::PathBuf,
+};
+
+#[cfg(feature = "backtrace")]
+use color_backtrace::{termcolor::NoColor, BacktracePrinter, Frame};
+
+#[cfg(feature = "backtrace")]
+use crate::error::Backtrace;
+
+static FORMAT_OPTIONS: RwLock<Option<FormatOptions>> = RwLock::new(None);
+
+static MESSAGE_STRINGS: RwLock<Option<Arc<MessageStrings>>> = RwLock::new(None);
+
+#[cfg(feature = "backtrace")]
+type FrameFilter = dyn Fn(&mut Vec<&Frame>) + Send + Sync + 'static;
+
+/// Identifies a frame by its function name and file:line, ignoring the address.
+#[cfg(feature = "backtrace")]
+pub(crate) type FrameKey = (Option<String>, Option<PathBuf>, Option<u32>);
+
+#[cfg(feature = "backtrace")]
+static FRAME_FILTERS: RwLock<Vec<Arc<FrameFilter>>> = RwLock::new(Vec::new());
+
+#[cfg(feature = "backtrace")]
+thread_local! {
+    /// Frames cut off by [`FormatOptions::max_frames`] in the trace being formatted.
+    static OMITTED_FRAMES: Cell<usize> = const { Cell::new(0) };
+    /// Frames of the previously printed trace, set while formatting with
+    /// [`FormatOptions::format_trace_after`].
+    static PREVIOUS_FRAMES: RefCell<Option<Vec<FrameKey>>> = const { RefCell::new(None) };
+    /// Frames elided as common with the previous trace.
+    static COMMON_FRAMES: Cell<usize> = const { Cell::new(0) };
+}
+
+#[cfg(feature = "backtrace")]
+pub(crate) fn frame_key(frame: &Frame) -> FrameKey {
+    (frame.name.clone(), frame.filename.clone(), frame.lineno)
+}
+
+/// Options controlling how errors are rendered by their `Debug` impl.
+///
+/// Every field left as `None` falls back to the environment, e.g. the verbosity
+/// is read from `N0_ERROR_VERBOSITY` or else `RUST_BACKTRACE`.
+#[derive(Debug, Clone, Default)]
+pub struct FormatOptions {
+    /// Overrides the verbosity.
+    ///
+    /// [`Verbosity::Minimal`] only prints the message chain, without span traces
+    /// and backtraces. [`Verbosity::Full`] also prints frames of dependencies.
+    ///
+    /// Defaults to `N0_ERROR_VERBOSITY`, one of `minimal`, `normal` or `full`,
+    /// which unlike `RUST_BACKTRACE` does not affect the panic hook. If neither
+    /// is set, `RUST_BACKTRACE` only controls the backtraces as before.
+    pub verbosity: Option<Verbosity>,
+    /// Whether backtraces and help notes are rendered with ANSI colors.
+    ///
+    /// Defaults to enabled, unless the `NO_COLOR` environment variable is set.
+    pub colors: Option<bool>,
+    /// Caps the length of each message in the chain, in chars.
+    ///
+    /// Longer messages are cut off and end with `…`.
+    pub max_message_len: Option<usize>,
+    /// Caps the number of sources listed below the message.
+    pub max_sources: Option<usize>,
+    /// Caps the number of frames printed for each backtrace.
+    pub max_frames: Option<usize>,
+    /// Renders each backtrace compactly, as only its first N application frames.
+    ///
+    /// Each frame takes a single line with its `file:line`, without colors, and
+    /// the number of further application frames is noted as `(+M frames)`.
+    /// Frames of dependencies and the standard library are left out.
+    pub compact_frames: Option<usize>,
+    /// Renders errors independent of the build, for snapshot tests.
+    ///
+    /// Backtraces are replaced with `<backtrace omitted>`, and line numbers as well
+    /// as the creation time and sequence number are left out. The message chain
+    /// is kept as is. Defaults to enabled if `N0_DETERMINISTIC_ERRORS` is `1`.
+    pub deterministic: Option<bool>,
+}
+
+/// Sets the process wide [`FormatOptions`] used when formatting errors.
+pub fn set_format_options(options: FormatOptions) {
+    let mut current = FORMAT_OPTIONS.write().unwrap_or_else(|e| e.into_inner());
+    *current = Some(options);
+}
+
+/// Registers an additional filter for the frames of printed backtraces.
+///
+/// Filters are applied after the built-in ones, in the order they were registered.
+#[cfg(feature = "backtrace")]
+pub fn add_frame_filter(filter: impl Fn(&mut Vec<&Frame>) + Send + Sync + 'static) {
+    let mut filters = FRAME_FILTERS.write().unwrap_or_else(|e| e.into_inner());
+    filters.push(Arc::new(filter));
+}
+
+impl FormatOptions {
+    /// Returns the currently configured options.
+    pub fn current() -> Self {
+        FORMAT_OPTIONS
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+            .unwrap_or_default()
+    }
+
+    /// Returns the currently configured options, with colors disabled.
+    pub fn plain() -> Self {
+        Self {
+            colors: Some(false),
+            ..Self::current()
+        }
+    }
+
+    pub(crate) fn verbosity(&self) -> Verbosity {
+        self.verbosity
+            .or_else(verbosity_from_env)
+            .unwrap_or_else(Verbosity::from_env)
+    }
+
+    /// Whether only the message chain is printed, see [`FormatOptions::verbosity`].
+    pub(crate) fn chain_only(&self) -> bool {
+        self.verbosity.or_else(verbosity_from_env) == Some(Verbosity::Minimal)
+    }
+
+    pub(crate) fn deterministic(&self) -> bool {
+        self.deterministic.unwrap_or_else(deterministic_from_env)
+    }
+
+    pub(crate) fn colors(&self) -> bool {
+        self.colors.unwrap_or_else(|| !no_color())
+    }
+
+    /// Builds the printer with the built-in and registered frame filters.
+    #[cfg(feature = "backtrace")]
+    pub(crate) fn printer(&self) -> BacktracePrinter {
+        let filters = [
+            "<n0_snafu::error::Error",
+            "n0_snafu::error::Error::anyhow",
+            "<core::pin::Pin<P> as core::future::future::Future>::poll",
+            "<core::result::Result<T,F> as core::ops::try_trait::FromResidual<core::result::Result<core::convert::Infallible,E>>>::from_residual",
+        ];
+
+        let mut printer = BacktracePrinter::new().add_frame_filter(Box::new(move |frames| {
+            frames.retain(|frame| {
+                frame
+                    .name
+                    .as_ref()
+                    .map(|name| {
+                        for f in &filters {
+                            if name.starts_with(f) {
+                                return false;
+                            }
+                        }
+                        true
+                    })
+                    .unwrap_or(true)
+            })
+        }));
+
+        if self.verbosity() != Verbosity::Full {
+            printer = printer.add_frame_filter(Box::new(|frames| {
+                frames.retain(|frame| !frame.is_dependency_code())
+            }));
+        }
+
+        let registered = FRAME_FILTERS
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        for filter in registered {
+            printer = printer.add_frame_filter(Box::new(move |frames| filter(frames)));
+        }
+
+        printer = printer.add_frame_filter(Box::new(|frames| {
+            PREVIOUS_FRAMES.with_borrow_mut(|previous| {
+                let Some(previous) = previous else {
+                    return;
+                };
+                let current: Vec<_> = frames.iter().map(|frame| frame_key(frame)).collect();
+                let common = current
+                    .iter()
+                    .rev()
+                    .zip(previous.iter().rev())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                COMMON_FRAMES.with(|c| c.set(common));
+                frames.truncate(frames.len() - common);
+                *previous = current;
+            })
+        }));
+
+        if let Some(max) = self.max_frames {
+            printer = printer.add_frame_filter(Box::new(move |frames| {
+                OMITTED_FRAMES.with(|omitted| omitted.set(frames.len().saturating_sub(max)));
+                frames.truncate(max);
+            }));
+        }
+
+        printer
+    }
+
+    #[cfg(feature = "backtrace")]
+    pub(crate) fn format_trace(&self, printer: &BacktracePrinter, bt: &Backtrace<'_>) -> String {
+        OMITTED_FRAMES.with(|omitted| omitted.set(0));
+        let mut trace = if self.colors() {
+            printer.format_trace_to_string(bt).unwrap()
+        } else {
+            let mut out = NoColor::new(Vec::new());
+            printer.print_trace(bt, &mut out).unwrap();
+            String::from_utf8(out.into_inner()).unwrap()
+        };
+        let omitted = OMITTED_FRAMES.with(|omitted| omitted.get());
+        if omitted > 0 {
+            trace.push_str(&format!("  ... ({omitted} more)\n"));
+        }
+        trace
+    }
+
+    /// Formats the first `n` application frames of `bt`, see [`FormatOptions::compact_frames`].
+    #[cfg(feature = "backtrace")]
+    pub(crate) fn format_compact(&self, bt: &Backtrace<'_>, n: usize) -> String {
+        use std::fmt::Write;
+
+        let frames = color_backtrace::Backtrace::frames(bt);
+        let frames: Vec<_> = frames
+            .iter()
+            .filter(|frame| !frame.is_dependency_code())
+            .collect();
+        let mut trace = String::new();
+        for frame in frames.iter().take(n) {
+            let name = frame.name.as_deref().unwrap_or("<unknown>");
+            write!(trace, "    {name}").ok();
+            if let (Some(file), Some(line)) = (&frame.filename, frame.lineno) {
+                write!(trace, " at {}:{line}", file.display()).ok();
+            }
+            trace.push('\n');
+        }
+        if frames.len() > n {
+            writeln!(trace, "    (+{} frames)", frames.len() - n).ok();
+        }
+        trace
+    }
+
+    /// Like [`format_trace`](Self::format_trace), but elides the outermost frames
+    /// shared with the trace formatted before, whose frames are kept in `previous`.
+    ///
+    /// Frames only count as shared if function name, file and line all match.
+    #[cfg(feature = "backtrace")]
+    pub(crate) fn format_trace_after(
+        &self,
+        printer: &BacktracePrinter,
+        bt: &Backtrace<'_>,
+        previous: &mut Vec<FrameKey>,
+    ) -> String {
+        COMMON_FRAMES.with(|common| common.set(0));
+        PREVIOUS_FRAMES.set(Some(std::mem::take(previous)));
+        let mut trace = self.format_trace(printer, bt);
+        *previous = PREVIOUS_FRAMES.take().unwrap_or_default();
+        let common = COMMON_FRAMES.with(|common| common.get());
+        if common > 0 {
+            trace.push_str(&format!("  ... {common} common frames with previous ...\n"));
+        }
+        trace
+    }
+}
+
+/// Truncates `message` to at most `max` chars, appending `…` if it was cut off.
+pub(crate) fn truncate_message(message: &str, max: usize) -> Cow<'_, str> {
+    match message.char_indices().nth(max) {
+        Some((end, _)) => format!("{}…", &message[..end]).into(),
+        None => message.into(),
+    }
+}
+
+/// The fixed strings used when rendering errors, e.g. for localization.
+#[derive(Debug, Clone)]
+pub struct MessageStrings {
+    /// Label of the error itself in its stack.
+    pub root: Cow<'static, str>,
+    /// Shown for errors without any message or source.
+    pub error: Cow<'static, str>,
+    /// Shown when an [`Option`] was `None`.
+    pub none: Cow<'static, str>,
+    /// Header of the span trace section.
+    pub span_trace: Cow<'static, str>,
+    /// Prefix of the header of each backtrace.
+    pub backtrace_for: Cow<'static, str>,
+    /// Note for backtraces identical to the previous one.
+    pub same_as_above: Cow<'static, str>,
+    /// Shown after the number of errors combined in an [`Error::Multiple`](crate::Error::Multiple).
+    pub multiple: Cow<'static, str>,
+    /// Shown instead of backtraces on targets which cannot capture them, e.g. wasm.
+    pub backtrace_unsupported: Cow<'static, str>,
+    /// Shown with full verbosity if span traces cannot be captured, as the
+    /// subscriber lacks an `ErrorLayer`.
+    pub span_trace_unsupported: Cow<'static, str>,
+    /// Shown where a chain of sources was cut off, as it seemed to loop.
+    pub cycle: Cow<'static, str>,
+}
+
+impl Default for MessageStrings {
+    fn default() -> Self {
+        Self {
+            root: "Root".into(),
+            error: "unknown error".into(),
+            none: "Expected some, found none".into(),
+            span_trace: "Span trace:".into(),
+            backtrace_for: "Backtrace for".into(),
+            same_as_above: "(same as above)".into(),
+            multiple: "errors occurred".into(),
+            backtrace_unsupported: "Backtraces are not available on this target".into(),
+            span_trace_unsupported: "span trace unavailable: no ErrorLayer registered".into(),
+            cycle: "(cycle detected)".into(),
+        }
+    }
+}
+
+/// Replaces the process wide [`MessageStrings`], which default to English.
+pub fn set_message_strings(strings: MessageStrings) {
+    let mut current = MESSAGE_STRINGS.write().unwrap_or_else(|e| e.into_inner());
+    *current = Some(Arc::new(strings));
+}
+
+impl MessageStrings {
+    /// Returns the currently configured strings.
+    pub fn current() -> Arc<Self> {
+        static DEFAULT: OnceLock<Arc<MessageStrings>> = OnceLock::new();
+        let current = MESSAGE_STRINGS.read().unwrap_or_else(|e| e.into_inner());
+        match current.as_ref() {
+            Some(strings) => strings.clone(),
+            None => DEFAULT.get_or_init(Default::default).clone(),
+        }
+    }
+}
+
+/// How much of an error is rendered, as in `color_backtrace`.
+///
+/// This is the same type with and without the `backtrace` feature. Without it only
+/// [`Verbosity::Minimal`] makes a difference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Only the message chain.
+    Minimal,
+    /// Everything in `Minimal` and additionally the span trace.
+    Medium,
+    /// Everything in `Medium`, plus hints why the span trace is missing.
+    Full,
+}
+
+impl Verbosity {
+    /// Get the verbosity level from the `RUST_BACKTRACE` env variable.
+    pub fn from_env() -> Self {
+        match std::env::var("RUST_BACKTRACE").ok() {
+            Some(ref x) if x == "full" => Verbosity::Full,
+            Some(_) => Verbosity::Medium,
+            None => Verbosity::Minimal,
+        }
+    }
+}
+
+#[cfg(feature = "backtrace")]
+impl From<Verbosity> for color_backtrace::Verbosity {
+    fn from(verbosity: Verbosity) -> Self {
+        match verbosity {
+            Verbosity::Minimal => Self::Minimal,
+            Verbosity::Medium => Self::Medium,
+            Verbosity::Full => Self::Full,
+        }
+    }
+}
+
+/// The verbosity set through `N0_ERROR_VERBOSITY`, if any.
+fn verbosity_from_env() -> Option<Verbosity> {
+    static VERBOSITY: OnceLock<Option<Verbosity>> = OnceLock::new();
+    *VERBOSITY.get_or_init(|| parse_verbosity(&std::env::var("N0_ERROR_VERBOSITY").ok()?))
+}
+
+fn parse_verbosity(value: &str) -> Option<Verbosity> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "minimal" => Some(Verbosity::Minimal),
+        "normal" => Some(Verbosity::Medium),
+        "full" => Some(Verbosity::Full),
+        _ => None,
+    }
+}
+
+/// Whether `N0_DETERMINISTIC_ERRORS` is set to `1`.
+fn deterministic_from_env() -> bool {
+    static DETERMINISTIC: OnceLock<bool> = OnceLock::new();
+    *DETERMINISTIC.get_or_init(|| std::env::var("N0_DETERMINISTIC_ERRORS").is_ok_and(|v| v == "1"))
+}
+
+/// Whether `NO_COLOR` is set to a non-empty value, see <https://no-color.org>.
+fn no_color() -> bool {
+    static NO_COLOR: OnceLock<bool> = OnceLock::new();
+    *NO_COLOR.get_or_init(no_color_from_env)
+}
+
+fn no_color_from_env() -> bool {
+    std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate_message() {
+        assert_eq!(truncate_message("short", 5), "short");
+        assert_eq!(truncate_message("longer", 4), "long…");
+        assert_eq!(truncate_message("grüße", 3), "grü…");
+    }
+
+    #[test]
+    fn test_parse_verbosity() {
+        assert_eq!(parse_verbosity("minimal"), Some(Verbosity::Minimal));
+        assert_eq!(parse_verbosity("normal"), Some(Verbosity::Medium));
+        assert_eq!(parse_verbosity(" Full "), Some(Verbosity::Full));
+        assert_eq!(parse_verbosity("1"), None);
+    }
+
+    #[cfg(feature = "backtrace")]
+    mod my_app {
+        #[inline(never)]
+        pub fn glue() -> snafu::Backtrace {
+            snafu::Backtrace::new()
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "backtrace")]
+    fn test_add_frame_filter() {
+        add_frame_filter(|frames| {
+            frames.retain(|frame| {
+                !frame
+                    .name
+                    .as_ref()
+                    .is_some_and(|name| name.contains("my_app::glue"))
+            })
+        });
+
+        let bt = my_app::glue();
+        let opts = FormatOptions {
+            colors: Some(false),
+            ..Default::default()
+        };
+        let s = opts.format_trace(&opts.printer(), &Backtrace::Crate(&bt));
+        assert!(!s.contains("my_app::glue"));
+    }
+
+    #[test]
+    #[cfg(feature = "backtrace")]
+    fn test_max_frames() {
+        let opts = FormatOptions {
+            colors: Some(false),
+            verbosity: Some(Verbosity::Full),
+            max_frames: Some(2),
+            ..Default::default()
+        };
+        let bt = snafu::Backtrace::new();
+        let s = opts.format_trace(&opts.printer(), &Backtrace::Crate(&bt));
+        assert!(s.contains(" more)\n"), "{s}");
+    }
+
+    #[cfg(feature = "backtrace")]
+    #[inline(never)]
+    fn capture_inner() -> snafu::Backtrace {
+        snafu::Backtrace::new()
+    }
+
+    #[cfg(feature = "backtrace")]
+    #[inline(never)]
+    fn capture_pair() -> (snafu::Backtrace, snafu::Backtrace) {
+        let inner = capture_inner();
+        let outer = snafu::Backtrace::new();
+        (inner, outer)
+    }
+
+    #[test]
+    #[cfg(feature = "backtrace")]
+    fn test_common_frames() {
+        let opts = FormatOptions {
+            colors: Some(false),
+            verbosity: Some(Verbosity::Full),
+            ..Default::default()
+        };
+        let printer = opts.printer();
+        let (inner, outer) = capture_pair();
+
+        let mut previous = Vec::new();
+        let s = opts.format_trace_after(&printer, &Backtrace::Crate(&outer), &mut previous);
+        assert!(!s.contains("common frames"), "{s}");
+        assert!(s.contains("test_common_frames"), "{s}");
+
+        let s = opts.format_trace_after(&printer, &Backtrace::Crate(&inner), &mut previous);
+        assert!(s.contains("common frames with previous"), "{s}");
+        assert!(s.contains("capture_inner"), "{s}");
+        assert!(s.contains("capture_pair"), "{s}");
+        assert!(!s.contains("test_common_frames"), "{s}");
+
+        // without a previous trace nothing is elided
+        let s = opts.format_trace(&printer, &Backtrace::Crate(&inner));
+        assert!(!s.contains("common frames"), "{s}");
+        assert!(s.contains("test_common_frames"), "{s}");
+    }
+}
@@ -0,0 +1,111 @@
+use std::{future::Future, panic::Location};
+
+use crate::{Error, Result, ResultExt};
+
+/// Adds context to the output of fallible futures.
+pub trait FutureExt<T> {
+    /// Like [`ResultExt::context`], applied once the future resolves.
+    ///
+    /// The recorded [`Error::location`] is the location of this call, not of the `.await`.
+    #[track_caller]
+    fn context<C>(self, context: C) -> impl Future<Output = Result<T, Error>>
+    where
+        C: AsRef<str>;
+}
+
+impl<F, T, E> FutureExt<T> for F
+where
+    F: Future<Output = Result<T, E>>,
+    Result<T, E>: ResultExt<T>,
+{
+    #[track_caller]
+    fn context<C>(self, context: C) -> impl Future<Output = Result<T, Error>>
+    where
+        C: AsRef<str>,
+    {
+        let location = Location::caller();
+        async move {
+            self.await.context(context).map_err(|mut err| {
+                err.set_location(location);
+                err
+            })
+        }
+    }
+}
+
+/// Adds context to the result of awaiting a [`tokio::task::JoinHandle`].
+#[cfg(feature = "tokio")]
+pub trait JoinResultExt<T> {
+    /// Flattens a failed join and the task's own error into one [`Error`] with `context` on top.
+    ///
+    /// A panicked or cancelled task becomes an [`Error::Whatever`] describing the
+    /// [`JoinError`](tokio::task::JoinError).
+    #[track_caller]
+    fn join_context<C>(self, context: C) -> Result<T, Error>
+    where
+        C: AsRef<str>;
+}
+
+#[cfg(feature = "tokio")]
+impl<T> JoinResultExt<T> for Result<Result<T, Error>, tokio::task::JoinError> {
+    #[track_caller]
+    fn join_context<C>(self, context: C) -> Result<T, Error>
+    where
+        C: AsRef<str>,
+    {
+        let res = match self {
+            Ok(res) => res,
+            Err(err) => Err(snafu::FromString::without_source(err.to_string())),
+        };
+        res.context(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format_err;
+
+    #[tokio::test]
+    async fn test_future_context() {
+        async fn fail_io() -> std::io::Result<()> {
+            Err(std::io::Error::other("sad IO"))
+        }
+
+        async fn fail() -> Result {
+            Err(format_err!("sad"))
+        }
+
+        let fut = fail_io().context("reading");
+        let line = line!() - 1;
+        let err = fut.await.unwrap_err();
+        assert_eq!(err.to_string(), "reading: sad IO");
+        assert_eq!(err.location().unwrap().line(), line);
+
+        let err = fail().context("outer").await.unwrap_err();
+        assert_eq!(err.to_string(), "outer: sad");
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_join_context() {
+        let res = tokio::spawn(async { Err::<(), _>(format_err!("sad")) }).await;
+        let err = res.join_context("worker").unwrap_err();
+        assert_eq!(err.display_chain(), "1: worker\n2: sad");
+
+        let res = tokio::spawn(async {
+            if true {
+                panic!("boom");
+            }
+            Ok(())
+        })
+        .await;
+        let err = res.join_context("worker").unwrap_err();
+        let chain = err.display_chain();
+        assert!(chain.starts_with("1: worker\n2: task "), "{chain}");
+        assert!(
+            chain.contains("panicked") && chain.contains("boom"),
+            "{chain}"
+        );
+    }
+}
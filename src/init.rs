@@ -0,0 +1,69 @@
+use std::sync::Once;
+
+#[cfg(feature = "tracing")]
+use tracing_error::ErrorLayer;
+#[cfg(feature = "tracing")]
+use tracing_subscriber::{
+    layer::{Layered, SubscriberExt},
+    registry::LookupSpan,
+};
+
+/// Adds an `ErrorLayer` to `subscriber`, so that errors capture span traces.
+///
+/// Use this when configuring your own subscriber, e.g.
+/// `with_error_layer(tracing_subscriber::registry().with(fmt_layer))`. The layer
+/// requires a subscriber which keeps track of spans, like the registry does.
+#[cfg(feature = "tracing")]
+pub fn with_error_layer<S>(subscriber: S) -> Layered<ErrorLayer<S>, S>
+where
+    S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+{
+    subscriber.with(ErrorLayer::default())
+}
+
+/// Installs a default tracing subscriber with an `ErrorLayer` and a colored panic hook,
+/// see `install_panic_hook`.
+///
+/// Without an `ErrorLayer` span traces are never captured. Applications that
+/// configure their own subscriber should not call this, but add an `ErrorLayer`
+/// to their subscriber instead, see `with_error_layer`.
+///
+/// Without the `tracing` feature only the panic hook is installed.
+///
+/// Calling this more than once has no further effect.
+pub fn init() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        #[cfg(feature = "tracing")]
+        {
+            let subscriber = with_error_layer(tracing_subscriber::fmt().finish());
+            // Another subscriber may have been set already, which we leave alone.
+            tracing::subscriber::set_global_default(subscriber).ok();
+        }
+
+        #[cfg(not(target_family = "wasm"))]
+        crate::install_panic_hook();
+        #[cfg(all(target_family = "wasm", feature = "backtrace"))]
+        crate::FormatOptions::current()
+            .printer()
+            .install(color_backtrace::default_output_stream());
+    });
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use tracing_error::SpanTraceStatus;
+
+    use super::*;
+
+    #[test]
+    fn test_with_error_layer() {
+        let subscriber = with_error_layer(tracing_subscriber::registry());
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("test_with_error_layer");
+            let _guard = span.enter();
+            let err = crate::format_err!("failed");
+            assert_eq!(err.span_trace().status(), SpanTraceStatus::CAPTURED);
+        });
+    }
+}
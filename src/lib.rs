@@ -1,8 +1,41 @@
+#[cfg(feature = "miette")]
+mod diagnostic;
 mod error;
+mod format;
+mod future;
+mod init;
+mod owned;
+mod panic;
+pub mod prelude;
+#[cfg(feature = "serde")]
+mod snapshot;
 mod spantrace;
+#[cfg(feature = "backtrace")]
+pub use color_backtrace::Frame;
+#[cfg(feature = "tracing")]
 pub use tracing_error::ErrorLayer;
 
+#[cfg(feature = "backtrace")]
+pub use self::format::add_frame_filter;
+#[cfg(feature = "tokio")]
+pub use self::future::JoinResultExt;
+#[cfg(feature = "tracing")]
+pub use self::init::with_error_layer;
+#[cfg(not(target_family = "wasm"))]
+pub use self::panic::install_panic_hook;
+#[cfg(feature = "serde")]
+pub use self::snapshot::ErrorSnapshot;
 pub use self::{
-    error::{Error, Result, ResultExt},
-    spantrace::SpanTrace,
+    error::{
+        register_context_provider, set_capture_creation, set_max_context_depth, try_collect_errors,
+        Backtrace, Chain, ContextIfExt, DisplayResultExt, Error, ErrorBuilder, ErrorCode,
+        ErrorKind, FlattenResultExt, FormattedResultExt, Meta, ResolvedBacktrace, Result,
+        ResultExt, Source, StdResultExt,
+    },
+    format::{set_format_options, set_message_strings, FormatOptions, MessageStrings, Verbosity},
+    future::FutureExt,
+    init::init,
+    owned::OwnedError,
+    panic::catch,
+    spantrace::{set_display_span_field, set_span_trace_baseline, SpanInfo, SpanTrace},
 };
@@ -0,0 +1,108 @@
+use crate::{Error, MessageStrings};
+
+/// An owned copy of an [`Error`], with its chain, span trace and backtrace
+/// flattened into strings, so that it can be cloned.
+#[derive(Clone)]
+pub struct OwnedError {
+    chain: Vec<String>,
+    span_trace: Option<String>,
+    backtrace: Option<String>,
+}
+
+impl OwnedError {
+    /// The messages of all links, starting with the outermost one.
+    pub fn chain(&self) -> &[String] {
+        &self.chain
+    }
+
+    /// The formatted span trace, if one was captured.
+    pub fn span_trace(&self) -> Option<&str> {
+        self.span_trace.as_deref()
+    }
+
+    /// The formatted first backtrace, if any.
+    pub fn backtrace(&self) -> Option<&str> {
+        self.backtrace.as_deref()
+    }
+}
+
+impl Error {
+    /// Flattens this error into an [`OwnedError`].
+    pub fn to_owned_error(&self) -> OwnedError {
+        let span_trace = self
+            .span_trace()
+            .is_captured()
+            .then(|| self.span_trace().to_string());
+        #[cfg(not(feature = "backtrace"))]
+        let backtrace = None;
+        #[cfg(feature = "backtrace")]
+        let backtrace = self.backtrace().map(|bt| {
+            let opts = crate::FormatOptions::current();
+            opts.format_trace(&opts.printer(), &bt)
+        });
+
+        OwnedError {
+            chain: self.chain().map(|source| source.to_string()).collect(),
+            span_trace,
+            backtrace,
+        }
+    }
+}
+
+impl From<&Error> for OwnedError {
+    fn from(value: &Error) -> Self {
+        value.to_owned_error()
+    }
+}
+
+impl std::fmt::Display for OwnedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut chain = self.chain.iter();
+        if let Some(first) = chain.next() {
+            write!(f, "{first}")?;
+        }
+        for message in chain {
+            write!(f, ": {message}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for OwnedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let strings = MessageStrings::current();
+
+        write!(f, "{self}")?;
+        if let Some(span_trace) = &self.span_trace {
+            write!(f, "\n{}\n{span_trace}", strings.span_trace)?;
+        }
+        if let Some(backtrace) = &self.backtrace {
+            write!(
+                f,
+                "\n{} {}:\n{backtrace}",
+                strings.backtrace_for, strings.root
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for OwnedError {}
+
+#[cfg(test)]
+mod tests {
+    use crate::{format_err, Result, ResultExt};
+
+    #[test]
+    fn test_owned_error_clone() {
+        let res: Result = Err(format_err!("inner"));
+        let err = res.context("middle").context("outer").unwrap_err();
+
+        let owned = err.to_owned_error();
+        let copy = owned.clone();
+        assert_eq!(owned.to_string(), err.to_string());
+        assert_eq!(copy.to_string(), err.to_string());
+        assert_eq!(format!("{copy:?}"), format!("{owned:?}"));
+        assert_eq!(copy.chain(), ["outer", "middle", "inner"]);
+    }
+}
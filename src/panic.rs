@@ -0,0 +1,227 @@
+use std::{
+    any::Any,
+    cell::{Cell, RefCell},
+    panic::{Location, PanicHookInfo, UnwindSafe},
+    sync::{Arc, Mutex, Once},
+};
+
+use snafu::FromString;
+
+use crate::{Error, Result};
+
+/// Installs a panic hook which prints panics like the `Debug` impl of [`Error`].
+///
+/// The backtrace is printed with the same frame filters and colors, followed by
+/// the current span trace, if any. Without the `backtrace` feature only the panic
+/// message is printed before the span trace. Calling this more than once has no
+/// further effect.
+#[cfg(not(target_family = "wasm"))]
+pub fn install_panic_hook() {
+    use snafu::GenerateImplicitData;
+
+    use crate::{MessageStrings, SpanTrace};
+
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        #[cfg(feature = "backtrace")]
+        let handler = {
+            use color_backtrace::termcolor::{ColorChoice, StandardStream};
+
+            let opts = crate::FormatOptions::current();
+            let choice = if opts.colors() {
+                ColorChoice::Auto
+            } else {
+                ColorChoice::Never
+            };
+            opts.printer()
+                .into_panic_handler(StandardStream::stderr(choice))
+        };
+        #[cfg(not(feature = "backtrace"))]
+        let handler = |info: &PanicHookInfo<'_>| eprintln!("{info}");
+        std::panic::set_hook(Box::new(move |info| {
+            handler(info);
+            let span_trace: SpanTrace = GenerateImplicitData::generate();
+            if span_trace.is_captured() {
+                let spans = span_trace.format_relative();
+                if !spans.is_empty() {
+                    eprintln!("\n{}\n{spans}", MessageStrings::current().span_trace);
+                }
+            }
+        }));
+    });
+}
+
+thread_local! {
+    /// How many calls to [`catch`] are running on this thread.
+    static CATCH_DEPTH: Cell<usize> = const { Cell::new(0) };
+    /// Where the last panic on this thread inside [`catch`] happened, as `file:line`.
+    static PANIC_LOCATION: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+type PanicHook = Box<dyn Fn(&PanicHookInfo<'_>) + Sync + Send + 'static>;
+
+/// How many threads are inside [`catch`], and the hook to restore once none is.
+static CATCH_HOOK: Mutex<(usize, Option<Arc<PanicHook>>)> = Mutex::new((0, None));
+
+/// Records where a panic happened, if it happened inside [`catch`].
+fn record_panic_location(info: &PanicHookInfo<'_>) {
+    if CATCH_DEPTH.get() == 0 {
+        return;
+    }
+    if let Some(location) = info.location() {
+        let location = format!("{}:{}", location.file(), location.line());
+        PANIC_LOCATION.with_borrow_mut(|l| *l = Some(location));
+    }
+}
+
+/// Runs `f`, turning a panic into an [`Error`].
+///
+/// The message of the error is the panic message, its location is the call to
+/// `catch`. Where the panic happened is recorded in the field `panic_location`,
+/// see [`Error::fields`], which also works for payloads that are not strings.
+/// Errors returned by `f` are passed through unchanged.
+///
+/// To record the panic location, the outermost `catch` on a thread wraps the
+/// current panic hook, and the last one running on any thread restores it. A hook
+/// set while no `catch` is running stays in place and is wrapped by the next one.
+#[track_caller]
+pub fn catch<T>(f: impl FnOnce() -> Result<T> + UnwindSafe) -> Result<T> {
+    let location = Location::caller();
+
+    // the hook can't be changed while panicking, e.g. in a `Drop` impl
+    let outermost = CATCH_DEPTH.get() == 0 && !std::thread::panicking();
+    if outermost {
+        wrap_panic_hook();
+    }
+    CATCH_DEPTH.set(CATCH_DEPTH.get() + 1);
+    let res = std::panic::catch_unwind(f);
+    CATCH_DEPTH.set(CATCH_DEPTH.get() - 1);
+    if outermost {
+        restore_panic_hook();
+    }
+    let panic_location = PANIC_LOCATION.with_borrow_mut(Option::take);
+
+    res.unwrap_or_else(|payload| {
+        let mut err = Error::without_source(panic_message(payload.as_ref()));
+        err.set_location(location);
+        if let Some(panic_location) = panic_location {
+            err = err.with_field("panic_location", panic_location);
+        }
+        Err(err)
+    })
+}
+
+/// Wraps the current panic hook to record panic locations, unless another thread
+/// already did.
+fn wrap_panic_hook() {
+    let mut hook = CATCH_HOOK.lock().unwrap_or_else(|e| e.into_inner());
+    if hook.0 == 0 {
+        let previous = Arc::new(std::panic::take_hook());
+        let wrapped = previous.clone();
+        std::panic::set_hook(Box::new(move |info| {
+            record_panic_location(info);
+            wrapped(info);
+        }));
+        hook.1 = Some(previous);
+    }
+    hook.0 += 1;
+}
+
+/// Restores the panic hook wrapped by [`wrap_panic_hook`], once no other thread
+/// is inside [`catch`].
+fn restore_panic_hook() {
+    let mut hook = CATCH_HOOK.lock().unwrap_or_else(|e| e.into_inner());
+    hook.0 -= 1;
+    if hook.0 > 0 {
+        return;
+    }
+    // drops the wrapper and with it the other reference to the previous hook
+    drop(std::panic::take_hook());
+    if let Some(previous) = hook.1.take() {
+        match Arc::try_unwrap(previous) {
+            Ok(previous) => std::panic::set_hook(previous),
+            Err(previous) => std::panic::set_hook(Box::new(move |info| previous(info))),
+        }
+    }
+}
+
+/// The message of a panic payload, which is a `&str` or `String` for `panic!`.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<dyn Any>".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format_err;
+
+    #[test]
+    fn test_catch() {
+        assert_eq!(catch(|| Ok(1)).unwrap(), 1);
+
+        let returned = || Err::<(), _>(format_err!("returned"));
+        let line = line!() - 1;
+        let err = catch(returned).unwrap_err();
+        assert_eq!(err.to_string(), "returned");
+        // still the location of `format_err!`, not of `catch`
+        assert_eq!(err.location().unwrap().line(), line);
+
+        let line = line!() + 1;
+        let err = catch(|| -> Result<()> { panic!("boom {}", 42) }).unwrap_err();
+        assert_eq!(err.to_string(), "boom 42");
+        assert_eq!(err.location().unwrap().line(), line);
+
+        let err = catch(|| -> Result<()> { panic!("static") }).unwrap_err();
+        assert_eq!(err.to_string(), "static");
+
+        let err = catch(|| -> Result<()> { std::panic::panic_any(42) }).unwrap_err();
+        assert_eq!(err.to_string(), "Box<dyn Any>");
+    }
+
+    #[test]
+    fn test_catch_panic_location() {
+        let line = line!() + 1;
+        let err = catch(|| -> Result<()> { std::panic::panic_any(42i32) }).unwrap_err();
+        let location = format!("{}:{line}", file!());
+        assert_eq!(
+            err.fields(),
+            [("panic_location".to_string(), location.clone())]
+        );
+        assert!(format!("{err:?}").contains(&format!("panic_location = {location}")));
+
+        // the inner `catch` leaves recording for the outer one in place
+        let line = line!() + 3;
+        let err = catch(|| -> Result<()> {
+            catch(|| Ok(())).unwrap();
+            std::panic::panic_any(42i32)
+        })
+        .unwrap_err();
+        let location = format!("{}:{line}", file!());
+        assert_eq!(err.fields(), [("panic_location".to_string(), location)]);
+    }
+
+    #[test]
+    fn test_catch_threads() {
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                std::thread::spawn(move || {
+                    for _ in 0..20 {
+                        let line = line!() + 1;
+                        let err = catch(|| -> Result<()> { std::panic::panic_any(i) }).unwrap_err();
+                        let location = format!("{}:{line}", file!());
+                        assert_eq!(err.fields(), [("panic_location".to_string(), location)]);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+}
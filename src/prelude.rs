@@ -0,0 +1,3 @@
+//! Common imports, for use as `use n0_snafu::prelude::*;`.
+
+pub use crate::{context_fmt, ensure, format_err, try_ctx, Error, Result, ResultExt};
@@ -0,0 +1,115 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, SpanTrace};
+
+/// A serializable snapshot of an [`Error`], e.g. for structured logging.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorSnapshot {
+    /// The `Display` output of the error.
+    pub message: String,
+    /// The messages of all links, starting with the outermost one.
+    pub chain: Vec<String>,
+    /// The captured span trace, if any.
+    pub span_trace: Option<String>,
+    /// The frames of the first backtrace, if any.
+    pub backtrace: Option<Vec<String>>,
+}
+
+impl Error {
+    /// Captures the current state of this error into an [`ErrorSnapshot`].
+    pub fn snapshot(&self) -> ErrorSnapshot {
+        let span_trace = self
+            .span_trace()
+            .is_captured()
+            .then(|| self.span_trace().to_string());
+        #[cfg(not(feature = "backtrace"))]
+        let backtrace = None;
+        #[cfg(feature = "backtrace")]
+        let backtrace = self.backtrace().map(|bt| {
+            color_backtrace::Backtrace::frames(&bt)
+                .into_iter()
+                .map(|frame| frame.name.unwrap_or_else(|| "<unknown>".into()))
+                .collect()
+        });
+
+        ErrorSnapshot {
+            message: self.to_string(),
+            chain: self.chain().map(|source| source.to_string()).collect(),
+            span_trace,
+            backtrace,
+        }
+    }
+}
+
+impl Error {
+    /// Rebuilds an [`Error`] from a snapshot, e.g. one received from another process.
+    ///
+    /// Each message of the chain becomes an [`Error::Whatever`] link, so the
+    /// rebuilt error cannot be downcast to the original error types. The span
+    /// trace and backtrace of the snapshot are only kept for display by `Debug`.
+    pub fn from_snapshot(snapshot: ErrorSnapshot) -> Error {
+        let link = |message: String, source: Option<Error>| Error::Whatever {
+            message: Some(message.into()),
+            span_trace: SpanTrace::empty(),
+            source: source.map(Box::new),
+            backtrace: None,
+            location: None,
+            meta: None,
+        };
+        let mut messages = snapshot.chain.into_iter().rev();
+        let innermost = messages.next().unwrap_or(snapshot.message);
+        let err = messages.fold(link(innermost, None), |source, message| {
+            link(message, Some(source))
+        });
+
+        if snapshot.span_trace.is_none() && snapshot.backtrace.is_none() {
+            return err;
+        }
+        err.with_meta(|meta| {
+            meta.remote_span_trace = snapshot.span_trace;
+            #[cfg(feature = "backtrace")]
+            {
+                meta.remote_backtrace = snapshot.backtrace;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ErrorSnapshot;
+    use crate::{format_err, Error, Result, ResultExt};
+
+    #[test]
+    fn test_snapshot_json() {
+        let res: Result = Err(format_err!("inner"));
+        let err = res.context("middle").context("outer").unwrap_err();
+
+        let json = serde_json::to_value(err.snapshot()).unwrap();
+        assert_eq!(json["message"], "outer: middle: inner");
+        assert_eq!(json["chain"].as_array().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_from_snapshot() {
+        let res: Result = Err(format_err!("inner"));
+        let err = res.context("middle").context("outer").unwrap_err();
+
+        let json = serde_json::to_string(&err.snapshot()).unwrap();
+        let snapshot: ErrorSnapshot = serde_json::from_str(&json).unwrap();
+        let rebuilt = Error::from_snapshot(snapshot.clone());
+        assert_eq!(rebuilt.display_chain(), err.display_chain());
+        assert_eq!(rebuilt.to_string(), err.to_string());
+
+        let snapshot = ErrorSnapshot {
+            span_trace: Some("   0: app::handle".into()),
+            backtrace: Some(vec!["app::main".into()]),
+            ..snapshot
+        };
+        let fmt = format!("{:?}", Error::from_snapshot(snapshot));
+        assert!(fmt.contains("   0: app::handle"), "{fmt}");
+        // the frames are dropped without the `backtrace` feature
+        let backtrace = fmt.contains(" 0: app::main");
+        assert_eq!(backtrace, cfg!(feature = "backtrace"), "{fmt}");
+    }
+}
@@ -1,18 +1,119 @@
+#[cfg(feature = "tracing")]
+use std::{fmt::Write, sync::RwLock};
+
+#[cfg(feature = "tracing")]
+use tracing::callsite::Identifier;
+
+/// Callsites of the spans set as baseline, innermost first.
+#[cfg(feature = "tracing")]
+static BASELINE: RwLock<Vec<Identifier>> = RwLock::new(Vec::new());
+
+/// Omits the currently entered spans from the span traces printed by errors.
+///
+/// Call this inside a long-lived root span, e.g. a per-request span, so that
+/// only spans entered below it are printed. Spans are matched by their callsite,
+/// not their field values.
+///
+/// Without the `tracing` feature this does nothing.
+pub fn set_span_trace_baseline() {
+    #[cfg(feature = "tracing")]
+    {
+        let mut callsites = Vec::new();
+        tracing_error::SpanTrace::capture().with_spans(|metadata, _| {
+            callsites.push(metadata.callsite());
+            true
+        });
+        *BASELINE.write().unwrap_or_else(|e| e.into_inner()) = callsites;
+    }
+}
+
+#[cfg(feature = "tracing")]
+static DISPLAY_FIELD: RwLock<Option<&'static str>> = RwLock::new(None);
+
+/// Prefixes the `Display` of newly created errors with a field of the current span.
+///
+/// The value is taken from the innermost entered span recording `field`, e.g.
+/// `request_id`, when the error is created, and shown as `[request_id=abc] `.
+/// Pass `None` to turn this off again, which is the default.
+///
+/// Without the `tracing` feature this does nothing.
+pub fn set_display_span_field(field: Option<&'static str>) {
+    #[cfg(feature = "tracing")]
+    {
+        *DISPLAY_FIELD.write().unwrap_or_else(|e| e.into_inner()) = field;
+    }
+    #[cfg(not(feature = "tracing"))]
+    let _ = field;
+}
+
+/// Looks up the field set by [`set_display_span_field`] in the current spans.
+///
+/// String values are returned without their quotes.
+#[cfg(feature = "tracing")]
+pub(crate) fn display_span_field() -> Option<(&'static str, String)> {
+    let field = (*DISPLAY_FIELD.read().unwrap_or_else(|e| e.into_inner()))?;
+    if !tracing::dispatcher::has_been_set() {
+        return None;
+    }
+
+    let mut value = None;
+    tracing_error::SpanTrace::capture().with_spans(|_, fields| {
+        value = parse_fields(fields)
+            .into_iter()
+            .find_map(|(key, value)| (key == field).then_some(value));
+        value.is_none()
+    });
+    let value = value?;
+    let value = match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(unquoted) => unquoted.to_string(),
+        None => value,
+    };
+    Some((field, value))
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn display_span_field() -> Option<(&'static str, String)> {
+    None
+}
+
+#[cfg(feature = "tracing")]
 #[derive(Clone)]
 pub struct SpanTrace(tracing_error::SpanTrace);
 
+/// Without the `tracing` feature span traces are never captured.
+#[cfg(not(feature = "tracing"))]
+#[derive(Debug, Clone)]
+pub struct SpanTrace;
+
+#[cfg(feature = "tracing")]
 impl std::fmt::Debug for SpanTrace {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.0.fmt(f)
     }
 }
 
+#[cfg(feature = "tracing")]
 impl std::fmt::Display for SpanTrace {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.0.fmt(f)
     }
 }
 
+#[cfg(not(feature = "tracing"))]
+impl std::fmt::Display for SpanTrace {
+    fn fmt(&self, _f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl From<tracing_error::SpanTrace> for SpanTrace {
+    fn from(value: tracing_error::SpanTrace) -> Self {
+        Self(value)
+    }
+}
+
+#[cfg(feature = "tracing")]
 impl std::ops::Deref for SpanTrace {
     type Target = tracing_error::SpanTrace;
     fn deref(&self) -> &Self::Target {
@@ -20,8 +121,206 @@ impl std::ops::Deref for SpanTrace {
     }
 }
 
+/// A span recorded in a [`SpanTrace`], see [`SpanTrace::spans`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpanInfo {
+    pub name: &'static str,
+    pub target: &'static str,
+    /// The recorded fields, with values formatted as by the subscriber.
+    pub fields: Vec<(String, String)>,
+}
+
+#[cfg(feature = "tracing")]
+impl SpanTrace {
+    /// A span trace without any spans, which is free to create.
+    pub(crate) fn empty() -> Self {
+        Self(tracing_error::SpanTrace::new(tracing::Span::none()))
+    }
+
+    /// Whether spans were captured, which requires an `ErrorLayer` to be installed.
+    pub fn is_captured(&self) -> bool {
+        self.0.status() == tracing_error::SpanTraceStatus::CAPTURED
+    }
+
+    /// Whether a subscriber is set, but without an `ErrorLayer` to capture spans.
+    pub(crate) fn is_unsupported(&self) -> bool {
+        self.0.status() == tracing_error::SpanTraceStatus::UNSUPPORTED
+    }
+
+    /// Returns the recorded spans, starting with the innermost one.
+    pub fn spans(&self) -> Vec<SpanInfo> {
+        let mut spans = Vec::new();
+        self.0.with_spans(|metadata, fields| {
+            spans.push(SpanInfo {
+                name: metadata.name(),
+                target: metadata.target(),
+                fields: parse_fields(fields),
+            });
+            true
+        });
+        spans
+    }
+
+    /// Formats the spans entered below the baseline set by [`set_span_trace_baseline`].
+    pub(crate) fn format_relative(&self) -> String {
+        let baseline = BASELINE.read().unwrap_or_else(|e| e.into_inner());
+        if baseline.is_empty() {
+            return self.to_string();
+        }
+
+        let mut spans = Vec::new();
+        self.0.with_spans(|metadata, fields| {
+            spans.push((metadata, fields.to_string()));
+            true
+        });
+        if let Some(start) = spans.len().checked_sub(baseline.len()) {
+            let is_baseline = spans[start..]
+                .iter()
+                .zip(baseline.iter())
+                .all(|((metadata, _), callsite)| metadata.callsite() == *callsite);
+            if is_baseline {
+                spans.truncate(start);
+            }
+        }
+
+        // same layout as the `Display` impl of `tracing_error::SpanTrace`
+        let mut out = String::new();
+        for (i, (metadata, fields)) in spans.into_iter().enumerate() {
+            if i > 0 {
+                out.push('\n');
+            }
+            write!(out, "{i:>4}: {}::{}", metadata.target(), metadata.name()).ok();
+            if !fields.is_empty() {
+                write!(out, "\n           with {fields}").ok();
+            }
+            if let (Some(file), Some(line)) = (metadata.file(), metadata.line()) {
+                write!(out, "\n             at {file}:{line}").ok();
+            }
+        }
+        out
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+impl SpanTrace {
+    pub(crate) fn empty() -> Self {
+        Self
+    }
+
+    /// Whether spans were captured, which is never the case without the `tracing` feature.
+    pub fn is_captured(&self) -> bool {
+        false
+    }
+
+    pub(crate) fn is_unsupported(&self) -> bool {
+        false
+    }
+
+    /// Returns the recorded spans, which are always empty without the `tracing` feature.
+    pub fn spans(&self) -> Vec<SpanInfo> {
+        Vec::new()
+    }
+
+    pub(crate) fn format_relative(&self) -> String {
+        String::new()
+    }
+}
+
+/// Splits fields formatted as `key=value`, separated by spaces, into pairs.
+///
+/// Spaces inside quoted values, as produced by `Debug` for strings, are kept.
+#[cfg(feature = "tracing")]
+fn parse_fields(fields: &str) -> Vec<(String, String)> {
+    let mut pairs = Vec::new();
+    let mut rest = fields.trim();
+    while !rest.is_empty() {
+        let mut quoted = false;
+        let mut escaped = false;
+        let end = rest
+            .char_indices()
+            .find(|&(_, c)| {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    '"' => quoted = !quoted,
+                    ' ' if !quoted => return true,
+                    _ => {}
+                }
+                false
+            })
+            .map_or(rest.len(), |(i, _)| i);
+        let (field, tail) = rest.split_at(end);
+        let (key, value) = field.split_once('=').unwrap_or((field, ""));
+        pairs.push((key.to_string(), value.to_string()));
+        rest = tail.trim_start();
+    }
+    pairs
+}
+
 impl snafu::GenerateImplicitData for SpanTrace {
     fn generate() -> Self {
-        Self(tracing_error::SpanTrace::capture())
+        #[cfg(test)]
+        CAPTURES.with(|c| c.set(c.get() + 1));
+        // Without any subscriber there is no `ErrorLayer` to record spans, so
+        // skip walking the span stack. The flag is sticky once a subscriber is set.
+        if !tracing::dispatcher::has_been_set() {
+            return Self::empty();
+        }
+        #[cfg(feature = "tracing")]
+        return Self(tracing_error::SpanTrace::capture());
+        #[cfg(not(feature = "tracing"))]
+        Self::empty()
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    static CAPTURES: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// Number of span traces captured on the current thread.
+#[cfg(test)]
+pub(crate) fn captures() -> usize {
+    CAPTURES.with(|c| c.get())
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_fields() {
+        assert_eq!(parse_fields(""), []);
+        assert_eq!(
+            parse_fields(r#"id=3 path="a b" quote="\" x""#),
+            [
+                ("id".to_string(), "3".to_string()),
+                ("path".to_string(), r#""a b""#.to_string()),
+                ("quote".to_string(), r#""\" x""#.to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let subscriber = tracing_subscriber::registry().with(crate::ErrorLayer::default());
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("handle_request", id = 7, peer = "a b");
+            let _guard = span.enter();
+
+            let span_trace: SpanTrace = snafu::GenerateImplicitData::generate();
+            let spans = span_trace.spans();
+            assert_eq!(spans.len(), 1);
+            assert_eq!(spans[0].name, "handle_request");
+            assert_eq!(
+                spans[0].fields,
+                [
+                    ("id".to_string(), "7".to_string()),
+                    ("peer".to_string(), r#""a b""#.to_string()),
+                ]
+            );
+        });
     }
 }
@@ -0,0 +1,51 @@
+#![cfg(feature = "backtrace")]
+
+use n0_snafu::{format_err, Backtrace, Error};
+use snafu::{ErrorCompat, Snafu};
+
+#[derive(Debug, Snafu)]
+#[snafu(display("inner"))]
+struct Inner {
+    backtrace: snafu::Backtrace,
+}
+
+/// Checks that `Error::backtrace` and `ErrorCompat::backtrace` return the same one.
+fn assert_same(err: &Error) -> &snafu::Backtrace {
+    let inherent = match err.backtrace() {
+        Some(Backtrace::Crate(bt) | Backtrace::Captured(bt, _)) => bt,
+        other => panic!("expected a snafu backtrace, got {other:?}"),
+    };
+    let compat = ErrorCompat::backtrace(err).expect("missing backtrace");
+    let frames = |bt: &snafu::Backtrace| bt.frames().iter().map(|f| f.ip()).collect::<Vec<_>>();
+    assert!(!inherent.frames().is_empty());
+    assert_eq!(frames(inherent), frames(compat));
+    assert!(std::ptr::eq(inherent, compat));
+    inherent
+}
+
+#[test]
+fn test_backtrace_accessors_agree() {
+    // before anything reads it, so that every link captures a backtrace
+    std::env::set_var("RUST_LIB_BACKTRACE", "1");
+
+    // the outermost backtrace wins
+    let err = format_err!("inner").context("middle").context("outer");
+    let bt = assert_same(&err);
+    let Some(Backtrace::Captured(own, _)) = err.stack().swap_remove(0).0 else {
+        panic!("expected the backtrace of the outer link");
+    };
+    assert!(std::ptr::eq(bt, own));
+
+    // without one of its own, the backtrace of the source is used
+    let err = Error::builder()
+        .source(InnerSnafu.build())
+        .backtrace(None)
+        .build();
+    assert!(matches!(err.backtrace(), Some(Backtrace::Crate(_))));
+    assert_same(&err);
+
+    // the std backtrace of anyhow is skipped by both, in favor of our own
+    let err = Error::anyhow(anyhow::anyhow!("any"));
+    assert!(matches!(err.backtrace(), Some(Backtrace::Captured(..))));
+    assert_same(&err);
+}
@@ -0,0 +1,23 @@
+use std::time::SystemTime;
+
+use n0_snafu::{format_err, set_capture_creation, ResultExt};
+
+#[test]
+fn test_capture_creation() {
+    let err = format_err!("not captured");
+    assert!(err.created_at().is_none());
+    assert!(err.thread_name().is_none());
+
+    set_capture_creation(true);
+    let before = SystemTime::now();
+    let err = std::thread::Builder::new()
+        .name("worker-1".into())
+        .spawn(|| "x".parse::<u32>().context("parse").unwrap_err())
+        .unwrap()
+        .join()
+        .unwrap();
+
+    assert_eq!(err.thread_name(), Some("worker-1"));
+    assert!(err.created_at().unwrap() >= before);
+    assert!(format!("{err:?}").contains(" on thread worker-1"));
+}
@@ -0,0 +1,24 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use n0_snafu::{catch, Result};
+
+/// Sets the process panic hook, so it runs in its own test binary.
+#[test]
+fn test_catch_keeps_later_hook() {
+    catch(|| Ok(())).unwrap();
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    std::panic::set_hook(Box::new(|_| {
+        CALLS.fetch_add(1, Ordering::SeqCst);
+    }));
+
+    let line = line!() + 1;
+    let err = catch(|| -> Result<()> { panic!("boom") }).unwrap_err();
+    let location = format!("{}:{line}", file!());
+    assert_eq!(err.fields(), [("panic_location".to_string(), location)]);
+    assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+
+    // the hook is back in place after `catch`
+    std::panic::catch_unwind(|| panic!("outside")).unwrap_err();
+    assert_eq!(CALLS.load(Ordering::SeqCst), 2);
+}
@@ -0,0 +1,12 @@
+use n0_snafu::{format_err, register_context_provider, ResultExt};
+
+#[test]
+fn test_context_provider() {
+    register_context_provider(|| vec![("tenant".into(), "acme".into())]);
+
+    let err = format_err!("failed");
+    assert_eq!(err.fields(), [("tenant".to_string(), "acme".to_string())]);
+
+    let err = "x".parse::<u32>().context("parse").unwrap_err();
+    assert_eq!(err.fields()[0].1, "acme");
+}
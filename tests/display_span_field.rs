@@ -0,0 +1,28 @@
+#![cfg(feature = "tracing")]
+
+use n0_snafu::{format_err, set_display_span_field, ErrorLayer, ResultExt};
+use tracing_subscriber::layer::SubscriberExt;
+
+#[test]
+fn test_display_span_field() {
+    let subscriber = tracing_subscriber::registry().with(ErrorLayer::default());
+    tracing::subscriber::with_default(subscriber, || {
+        let span = tracing::info_span!("handle_request", request_id = "abc");
+        let _guard = span.enter();
+
+        let err = format_err!("not configured");
+        assert_eq!(err.to_string(), "not configured");
+
+        set_display_span_field(Some("request_id"));
+        let inner = tracing::info_span!("inner", attempt = 2);
+        let _inner = inner.enter();
+        let err = Err::<(), _>(format_err!("inner"))
+            .context("outer")
+            .unwrap_err();
+        assert_eq!(err.span_field(), Some(("request_id", "abc")));
+        assert_eq!(err.to_string(), "[request_id=abc] outer: inner");
+
+        set_display_span_field(None);
+        assert_eq!(format_err!("off").to_string(), "off");
+    });
+}
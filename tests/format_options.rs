@@ -0,0 +1,25 @@
+use n0_snafu::{set_format_options, Error, FormatOptions};
+use snafu::Snafu;
+
+#[derive(Debug, Snafu)]
+#[snafu(display("sad"))]
+struct SadError;
+
+#[test]
+fn test_format_options_no_colors() {
+    set_format_options(FormatOptions {
+        colors: Some(false),
+        ..Default::default()
+    });
+
+    let err = Error::builder()
+        .source(SadError)
+        .backtrace(Some(snafu::Backtrace::new()))
+        .build()
+        .with_help("cheer up");
+
+    let fmt = format!("{err:?}");
+    assert!(fmt.starts_with("sad"), "{fmt}");
+    assert!(fmt.contains("help: cheer up"), "{fmt}");
+    assert!(!fmt.contains("\x1b["), "{fmt}");
+}
@@ -0,0 +1,16 @@
+#![cfg(feature = "tracing")]
+
+use n0_snafu::{init, SpanTrace};
+use tracing_error::SpanTraceStatus;
+
+/// In its own binary, as it installs a global subscriber and panic hook.
+#[test]
+fn test_init() {
+    init();
+    init();
+
+    let span = tracing::info_span!("test_init");
+    let _guard = span.enter();
+    let span_trace: SpanTrace = snafu::GenerateImplicitData::generate();
+    assert_eq!(span_trace.status(), SpanTraceStatus::CAPTURED);
+}
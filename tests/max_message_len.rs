@@ -0,0 +1,24 @@
+use n0_snafu::{format_err, set_format_options, FormatOptions, Result, ResultExt};
+
+#[test]
+fn test_max_message_len() {
+    set_format_options(FormatOptions {
+        max_message_len: Some(8),
+        colors: Some(false),
+        ..Default::default()
+    });
+
+    let res: Result = Err(format_err!("the inner message is long"));
+    let err = res.context("überlange Nachricht").unwrap_err();
+
+    let debug = format!("{err:?}");
+    let mut lines = debug.lines();
+    assert_eq!(lines.next(), Some("überlang…"));
+    assert_eq!(lines.next(), Some("  0: the inne…"));
+
+    // Display is not affected
+    assert_eq!(
+        err.to_string(),
+        "überlange Nachricht: the inner message is long"
+    );
+}
@@ -0,0 +1,24 @@
+use n0_snafu::{format_err, set_format_options, FormatOptions};
+
+#[test]
+fn test_max_sources() {
+    set_format_options(FormatOptions {
+        max_sources: Some(5),
+        colors: Some(false),
+        ..Default::default()
+    });
+
+    let mut err = format_err!("root cause");
+    for i in 0..49 {
+        err = err.context(format!("retry {i}"));
+    }
+    assert_eq!(err.chain().len(), 50);
+
+    let debug = format!("{err:?}");
+    let sources = debug
+        .lines()
+        .filter(|line| line.starts_with("  ") && line.contains(": retry"))
+        .count();
+    assert_eq!(sources, 5);
+    assert!(debug.contains("  ... (44 more)"), "{debug}");
+}
@@ -0,0 +1,31 @@
+use n0_snafu::{format_err, set_message_strings, MessageStrings, ResultExt};
+
+#[test]
+fn test_german_message_strings() {
+    set_message_strings(MessageStrings {
+        root: "Wurzel".into(),
+        error: "Fehler".into(),
+        none: "Wert erwartet, keiner gefunden".into(),
+        span_trace: "Span-Trace:".into(),
+        backtrace_for: "Backtrace für".into(),
+        same_as_above: "(wie oben)".into(),
+        multiple: "Fehler aufgetreten".into(),
+        backtrace_unsupported: "Backtraces sind auf dieser Plattform nicht verfügbar".into(),
+        span_trace_unsupported: "Span-Trace nicht verfügbar: kein ErrorLayer registriert".into(),
+        cycle: "(Zyklus erkannt)".into(),
+    });
+
+    let err = None::<()>.e().unwrap_err();
+    assert_eq!(err.to_string(), "Wert erwartet, keiner gefunden");
+
+    let err = Err::<(), _>(std::fmt::Error)
+        .e()
+        .unwrap_err()
+        .context("außen");
+    // the link added by `e()` has no message of its own
+    assert!(format!("{err:#}").contains("\n  0: Fehler\n"), "{err:#}");
+
+    let err = format_err!("kaputt");
+    let stack = err.stack();
+    assert_eq!(stack[0].1.to_string(), "Wurzel");
+}
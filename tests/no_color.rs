@@ -0,0 +1,22 @@
+use n0_snafu::Error;
+use snafu::Snafu;
+
+#[derive(Debug, Snafu)]
+#[snafu(display("sad"))]
+struct SadError;
+
+/// `NO_COLOR` is read once, so it is set before anything is formatted.
+#[test]
+fn test_no_color() {
+    std::env::set_var("NO_COLOR", "1");
+
+    let err = Error::builder()
+        .source(SadError)
+        .backtrace(Some(snafu::Backtrace::new()))
+        .build()
+        .with_help("cheer up");
+
+    let fmt = format!("{err:?}");
+    assert!(fmt.contains("help: cheer up"), "{fmt}");
+    assert!(!fmt.contains('\x1b'), "{fmt}");
+}
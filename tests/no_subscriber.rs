@@ -0,0 +1,21 @@
+#![cfg(feature = "tracing")]
+
+use n0_snafu::{format_err, ErrorLayer};
+use tracing_error::SpanTraceStatus;
+use tracing_subscriber::layer::SubscriberExt;
+
+#[test]
+fn test_span_trace_without_subscriber() {
+    // no subscriber has been set in this process yet
+    assert!(!tracing::dispatcher::has_been_set());
+    let err = format_err!("no subscriber");
+    assert_eq!(err.span_trace().status(), SpanTraceStatus::EMPTY);
+
+    let subscriber = tracing_subscriber::registry().with(ErrorLayer::default());
+    tracing::subscriber::with_default(subscriber, || {
+        let span = tracing::info_span!("with_subscriber");
+        let _guard = span.enter();
+        let err = format_err!("subscriber");
+        assert_eq!(err.span_trace().status(), SpanTraceStatus::CAPTURED);
+    });
+}
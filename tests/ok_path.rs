@@ -0,0 +1,57 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+use n0_snafu::{format_err, Error, ResultExt};
+
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn allocations<R>(f: impl FnOnce() -> R) -> (R, usize) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let res = f();
+    (res, ALLOCATIONS.with(Cell::get) - before)
+}
+
+#[test]
+fn test_ok_path_does_not_allocate() {
+    let ok = || Ok::<_, std::fmt::Error>(1);
+    let (res, count) = allocations(|| ok().context("probe"));
+    assert_eq!((res.unwrap(), count), (1, 0));
+    let (res, count) = allocations(|| ok().with_context(|| unreachable!()));
+    assert_eq!((res.unwrap(), count), (1, 0));
+    let (res, count) = allocations(|| ok().e());
+    assert_eq!((res.unwrap(), count), (1, 0));
+
+    let ok = || Ok::<_, Error>(1);
+    let (res, count) = allocations(|| ok().context("probe"));
+    assert_eq!((res.unwrap(), count), (1, 0));
+    let (res, count) = allocations(|| ok().with_context(|| unreachable!()));
+    assert_eq!((res.unwrap(), count), (1, 0));
+
+    let (res, count) = allocations(|| Some(1).with_context(|| unreachable!()));
+    assert_eq!((res.unwrap(), count), (1, 0));
+
+    // sanity check that the counter sees the error path
+    let (res, count) = allocations(|| Err::<(), _>(format_err!("fail")).context("probe"));
+    assert!(res.is_err());
+    assert!(count > 0);
+}
@@ -0,0 +1,36 @@
+#![cfg(feature = "tracing")]
+
+use std::process::Command;
+
+use n0_snafu::{install_panic_hook, ErrorLayer};
+use tracing_subscriber::layer::SubscriberExt;
+
+/// Runs itself in a child process, as the panic output goes to stderr.
+#[test]
+fn test_panic_hook() {
+    if std::env::var_os("N0_SNAFU_PANIC_CHILD").is_some() {
+        let subscriber = tracing_subscriber::registry().with(ErrorLayer::default());
+        tracing::subscriber::set_global_default(subscriber).unwrap();
+        install_panic_hook();
+        install_panic_hook();
+
+        let _guard = tracing::info_span!("panicking_span").entered();
+        panic!("boom");
+    }
+
+    let output = Command::new(std::env::current_exe().unwrap())
+        .args(["test_panic_hook", "--exact", "--nocapture"])
+        .env("N0_SNAFU_PANIC_CHILD", "1")
+        .env("RUST_BACKTRACE", "1")
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("boom"), "{stderr}");
+    // the frames, only printed with the `backtrace` feature
+    let frames = stderr.contains("test_panic_hook");
+    assert_eq!(frames, cfg!(feature = "backtrace"), "{stderr}");
+    assert!(stderr.contains("panicking_span"), "{stderr}");
+}
@@ -0,0 +1,20 @@
+use n0_snafu::prelude::*;
+
+fn parse(s: &str) -> Result<u32> {
+    ensure!(!s.is_empty(), "empty input");
+    let n = s.parse::<u32>().context("invalid number")?;
+    if n == 0 {
+        return Err(format_err!("zero"));
+    }
+    Ok(n)
+}
+
+#[test]
+fn test_prelude() {
+    assert_eq!(parse("3").unwrap(), 3);
+    assert_eq!(parse("").unwrap_err().to_string(), "empty input");
+    assert_eq!(parse("0").unwrap_err().to_string(), "zero");
+
+    let err: Error = parse("x").unwrap_err();
+    assert!(err.to_string().starts_with("invalid number"));
+}
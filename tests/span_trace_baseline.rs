@@ -0,0 +1,24 @@
+#![cfg(feature = "tracing")]
+
+use n0_snafu::{format_err, set_span_trace_baseline, ErrorLayer};
+use tracing_subscriber::layer::SubscriberExt;
+
+#[test]
+fn test_span_trace_baseline() {
+    let subscriber = tracing_subscriber::registry().with(ErrorLayer::default());
+    tracing::subscriber::with_default(subscriber, || {
+        let root = tracing::info_span!("ambient_root");
+        let _root = root.enter();
+        set_span_trace_baseline();
+
+        let nested = tracing::info_span!("nested_work");
+        let _nested = nested.enter();
+        let err = format_err!("failed");
+
+        let debug = format!("{err:?}");
+        assert!(debug.contains("nested_work"), "{debug}");
+        assert!(!debug.contains("ambient_root"), "{debug}");
+        // the full trace is still available
+        assert!(err.span_trace().to_string().contains("ambient_root"));
+    });
+}